@@ -0,0 +1,56 @@
+//! Embeds the git commit and the build date for `--version`
+
+#![allow(clippy::needless_return)]
+
+fn get_git_hash() -> Option<String> {
+    let output = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let hash = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    let dirty = std::process::Command::new("git")
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .output()
+        .is_ok_and(|x| !x.stdout.is_empty());
+    return Some(if dirty { format!("{hash}-dirty") } else { hash });
+}
+
+/// SOURCE_DATE_EPOCH keeps reproducible builds reproducible
+fn get_build_date() -> String {
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|x| x.parse::<i64>().ok())
+        .and_then(|x| chrono::DateTime::from_timestamp(x, 0))
+        .unwrap_or_else(chrono::Utc::now);
+    return timestamp.format("%Y-%m-%d").to_string();
+}
+
+/// Files whose change means a new commit. A commit on the current branch only
+/// updates the ref HEAD points to, or packed-refs. Missing files are skipped
+/// because cargo would rerun the script on every build
+fn get_git_watch_paths() -> Vec<String> {
+    let mut ret = vec![".git/HEAD".to_string(), ".git/index".to_string()];
+    if let Ok(head) = std::fs::read_to_string(".git/HEAD") {
+        if let Some(reference) = head.trim().strip_prefix("ref: ") {
+            ret.push(format!(".git/{reference}"));
+        }
+    }
+    ret.push(".git/packed-refs".to_string());
+    return ret
+        .into_iter()
+        .filter(|x| std::path::Path::new(x).exists())
+        .collect();
+}
+
+fn main() {
+    for path in get_git_watch_paths() {
+        println!("cargo:rerun-if-changed={path}");
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let git_hash = get_git_hash().unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_HASH={git_hash}");
+    println!("cargo:rustc-env=BUILD_DATE={}", get_build_date());
+}
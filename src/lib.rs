@@ -0,0 +1,631 @@
+//! Reading interface counters and computing their deltas. The ifstat-rs
+//! binary builds the rates, output formats and history handling on top of
+//! this crate
+
+#![allow(clippy::needless_return)]
+
+use anyhow::Context;
+use std::io::BufRead;
+
+/// Cumulative counters of one interface. Missing counters of older history
+/// files default to zero
+#[derive(Debug, Copy, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct DeviceStatistics {
+    /// Received bytes
+    pub rx: u64,
+    /// Transmitted bytes
+    pub tx: u64,
+    /// Received packets
+    #[serde(default)]
+    pub rx_packets: u64,
+    /// Transmitted packets
+    #[serde(default)]
+    pub tx_packets: u64,
+    /// Receive errors
+    #[serde(default)]
+    pub rx_errors: u64,
+    /// Transmit errors
+    #[serde(default)]
+    pub tx_errors: u64,
+    /// Dropped received packets
+    #[serde(default)]
+    pub rx_drops: u64,
+    /// Dropped transmitted packets
+    #[serde(default)]
+    pub tx_drops: u64,
+}
+
+/// Width of kernel counters which determines where they wrap around
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CounterBits {
+    /// Counters wrap around after u32::MAX
+    Bits32,
+    /// Counters never wrap in practice, a decrease is a reset
+    Bits64,
+}
+
+/// Increment of a counter from `old` to `new`, or None when it went backwards
+/// and cannot have wrapped around
+pub fn get_counter_delta(
+    new: u64,
+    old: u64,
+    counter_bits: CounterBits,
+) -> Option<u64> {
+    if new >= old {
+        return Some(new - old);
+    }
+    if counter_bits == CounterBits::Bits32 && old <= u64::from(u32::MAX) {
+        return Some(new + (u64::from(u32::MAX) - old) + 1);
+    }
+    return None;
+}
+
+impl DeviceStatistics {
+    /// Counter increments since `earlier`. A counter which went backwards is
+    /// assumed to wrap around if it is 32-bit wide, otherwise it was reset and
+    /// its delta is clamped to zero
+    pub fn delta_since(
+        &self,
+        earlier: &Self,
+        ifname: &str,
+        counter_bits: CounterBits,
+    ) -> Self {
+        let delta = |name: &str, new: u64, old: u64| -> u64 {
+            match get_counter_delta(new, old, counter_bits) {
+                Some(x) => x,
+                None => {
+                    log::warn!(
+                        "Counter '{name}' of '{ifname}' decreased from {old} to {new}. Assuming it was reset"
+                    );
+                    0
+                }
+            }
+        };
+        return Self {
+            rx: delta("rx bytes", self.rx, earlier.rx),
+            tx: delta("tx bytes", self.tx, earlier.tx),
+            rx_packets: delta("rx packets", self.rx_packets, earlier.rx_packets),
+            tx_packets: delta("tx packets", self.tx_packets, earlier.tx_packets),
+            rx_errors: delta("rx errs", self.rx_errors, earlier.rx_errors),
+            tx_errors: delta("tx errs", self.tx_errors, earlier.tx_errors),
+            rx_drops: delta("rx drop", self.rx_drops, earlier.rx_drops),
+            tx_drops: delta("tx drop", self.tx_drops, earlier.tx_drops),
+        };
+    }
+}
+
+impl std::ops::AddAssign for DeviceStatistics {
+    fn add_assign(&mut self, rhs: Self) {
+        self.rx += rhs.rx;
+        self.tx += rhs.tx;
+        self.rx_packets += rhs.rx_packets;
+        self.tx_packets += rhs.tx_packets;
+        self.rx_errors += rhs.rx_errors;
+        self.tx_errors += rhs.tx_errors;
+        self.rx_drops += rhs.rx_drops;
+        self.tx_drops += rhs.tx_drops;
+    }
+}
+impl std::ops::Add<DeviceStatistics> for DeviceStatistics {
+    type Output = Self;
+    fn add(self, rhs: DeviceStatistics) -> Self::Output {
+        let mut tmp = self;
+        tmp += rhs;
+        return tmp;
+    }
+}
+
+/// Counters or their deltas keyed by interface name
+pub type DeviceRates = std::collections::BTreeMap<String, DeviceStatistics>;
+
+/// Layout version of the history file. Files without it predate versioning
+/// and only differ by missing counters, which default to zero
+pub const DB_VERSION: u32 = 1;
+
+/// Snapshot of all interface counters, which is also the layout of the
+/// history file
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct StatisticsDb {
+    /// When the counters were read
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Counters keyed by interface name
+    pub devices: DeviceRates,
+    /// Layout version, zero for files written before versioning
+    #[serde(default)]
+    pub version: u32,
+    /// Interface names in the order the backend reported them
+    #[serde(skip)]
+    kernel_order: Vec<String>,
+}
+impl StatisticsDb {
+    /// Empty snapshot taken now
+    pub fn new() -> Self {
+        let timestamp = chrono::Utc::now();
+        let devices = DeviceRates::new();
+        return Self {
+            timestamp,
+            devices,
+            version: DB_VERSION,
+            kernel_order: Vec::new(),
+        };
+    }
+
+    /// Adds an interface, remembering the order of first insertion
+    pub fn insert(&mut self, ifname: String, stat: DeviceStatistics) {
+        if !self.devices.contains_key(&ifname) {
+            self.kernel_order.push(ifname.clone());
+        }
+        self.devices.insert(ifname, stat);
+    }
+
+    /// Interface names in the order they were first inserted. Empty for
+    /// snapshots read back from a history file
+    pub fn kernel_order(&self) -> &[String] {
+        return &self.kernel_order;
+    }
+
+    /// Keeps only the interfaces for which `f` returns true
+    pub fn retain(&mut self, mut f: impl FnMut(&str) -> bool) {
+        self.devices.retain(|k, _| f(k));
+        self.kernel_order.retain(|x| f(x));
+    }
+
+    /// Accepts the current layout and migrates unversioned files
+    pub fn check_version(mut self) -> anyhow::Result<Self> {
+        if self.version > DB_VERSION {
+            return Err(anyhow::anyhow!(
+                "History file has version {}, but this build only supports up to \
+                 version {}. Remove the file or upgrade ifstat-rs",
+                self.version,
+                DB_VERSION
+            ));
+        }
+        if self.version == 0 {
+            log::info!("Migrating unversioned history file to version {DB_VERSION}");
+            self.version = DB_VERSION;
+        }
+        return Ok(self);
+    }
+}
+
+impl Default for StatisticsDb {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// Default source of counters on Linux
+pub const PROC_NET_DEV_PATH: &str = "/proc/net/dev";
+
+/// Decides which interfaces are read from /proc/net/dev. When a name matches
+/// both patterns, exclude wins. A non-empty `names` list additionally keeps
+/// only the listed interfaces
+#[derive(Debug)]
+pub struct InterfaceFilter {
+    /// Interfaces to keep, or all when empty
+    pub names: Vec<String>,
+    /// Drop loopback interfaces
+    pub no_loopback: bool,
+    /// Keep only names matching this pattern
+    pub include: Option<regex::Regex>,
+    /// Drop names matching this pattern
+    pub exclude: Option<regex::Regex>,
+}
+impl InterfaceFilter {
+    /// Whether the interface passes every configured condition
+    pub fn is_selected(&self, ifname: &str) -> bool {
+        if self.no_loopback && is_loopback(ifname) {
+            return false;
+        }
+        if !self.names.is_empty() && !self.names.iter().any(|x| x == ifname) {
+            return false;
+        }
+        if let Some(re) = &self.exclude {
+            if re.is_match(ifname) {
+                return false;
+            }
+        }
+        if let Some(re) = &self.include {
+            return re.is_match(ifname);
+        }
+        return true;
+    }
+}
+
+/// ARPHRD_LOOPBACK from linux/if_arp.h
+const ARPHRD_LOOPBACK: &str = "772";
+
+/// Checks the link type in sysfs, falling back to the conventional loopback
+/// names when it is unavailable
+pub fn is_loopback(ifname: &str) -> bool {
+    let path = std::path::Path::new(SYS_CLASS_NET_PATH)
+        .join(ifname)
+        .join("type");
+    return match std::fs::read_to_string(path) {
+        Ok(x) => x.trim() == ARPHRD_LOOPBACK,
+        Err(_) => is_loopback_name(ifname),
+    };
+}
+
+/// Name based guess where sysfs is unavailable: `lo` on Linux, `lo0` on
+/// macOS and "Loopback Pseudo-Interface 1" on Windows
+fn is_loopback_name(ifname: &str) -> bool {
+    if let Some(suffix) = ifname.strip_prefix("lo") {
+        return suffix.chars().all(|x| x.is_ascii_digit());
+    }
+    return ifname.contains("Loopback");
+}
+
+/// Reads a file in the /proc/net/dev format. See parse_proc_net_dev_reader
+pub fn parse_proc_net_dev(
+    path: &str,
+    hide_zero_ifs: bool,
+    skip_bad_lines: bool,
+    filter: &InterfaceFilter,
+    rx_column: &str,
+    tx_column: &str,
+) -> anyhow::Result<StatisticsDb> {
+    let buf_reader = std::io::BufReader::new(
+        std::fs::File::open(path)
+            .with_context(|| format!("Failed to open {}", path))?,
+    );
+    return parse_proc_net_dev_reader(
+        buf_reader,
+        hide_zero_ifs,
+        skip_bad_lines,
+        filter,
+        rx_column,
+        tx_column,
+    );
+}
+
+/// Positions of the used counters after the interface name, taken from the
+/// second header line of /proc/net/dev. `rx` and `tx` are the columns feeding
+/// the byte counters, `bytes` unless another column was requested
+struct ProcNetDevColumns {
+    count: usize,
+    rx: usize,
+    rx_packets: usize,
+    rx_errs: usize,
+    rx_drop: usize,
+    tx: usize,
+    tx_packets: usize,
+    tx_errs: usize,
+    tx_drop: usize,
+}
+impl ProcNetDevColumns {
+    /// Parses a header like ` face |bytes packets ...|bytes packets ...`
+    fn from_header(
+        header: &str,
+        rx_column: &str,
+        tx_column: &str,
+    ) -> anyhow::Result<Self> {
+        let groups: Vec<Vec<&str>> = header
+            .split('|')
+            .skip(1)
+            .map(|x| x.split_ascii_whitespace().collect())
+            .collect();
+        let (rx, tx) = match groups.as_slice() {
+            [rx, tx] => (rx, tx),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Expected receive and transmit column groups in header '{}'",
+                    header
+                ))
+            }
+        };
+        let find = |group: &[&str], offset: usize, name: &str| {
+            return group
+                .iter()
+                .position(|x| *x == name)
+                .map(|x| x + offset)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Missing column '{name}' in header '{header}'")
+                });
+        };
+        return Ok(Self {
+            count: rx.len() + tx.len(),
+            rx: find(rx, 0, rx_column)?,
+            rx_packets: find(rx, 0, "packets")?,
+            rx_errs: find(rx, 0, "errs")?,
+            rx_drop: find(rx, 0, "drop")?,
+            tx: find(tx, rx.len(), tx_column)?,
+            tx_packets: find(tx, rx.len(), "packets")?,
+            tx_errs: find(tx, rx.len(), "errs")?,
+            tx_drop: find(tx, rx.len(), "drop")?,
+        });
+    }
+}
+
+/// Parses counters following the interface name on a /proc/net/dev line
+fn parse_proc_net_dev_counters(
+    counters: &[&str],
+    columns: &ProcNetDevColumns,
+) -> anyhow::Result<DeviceStatistics> {
+    if counters.len() != columns.count {
+        return Err(anyhow::anyhow!(
+            "Expected {} counters as in the header, got {}",
+            columns.count,
+            counters.len()
+        ));
+    }
+    let parse_counter = |index: usize, name: &str| -> anyhow::Result<u64> {
+        match counters.get(index) {
+            Some(x) => x
+                .parse::<u64>()
+                .with_context(|| format!("Failed to parse {name}")),
+            None => Err(anyhow::anyhow!("Missing {name}")),
+        }
+    };
+    return Ok(DeviceStatistics {
+        rx: parse_counter(columns.rx, "rx column")?,
+        tx: parse_counter(columns.tx, "tx column")?,
+        rx_packets: parse_counter(columns.rx_packets, "rx packets")?,
+        tx_packets: parse_counter(columns.tx_packets, "tx packets")?,
+        rx_errors: parse_counter(columns.rx_errs, "rx errs")?,
+        tx_errors: parse_counter(columns.tx_errs, "tx errs")?,
+        rx_drops: parse_counter(columns.rx_drop, "rx drop")?,
+        tx_drops: parse_counter(columns.tx_drop, "tx drop")?,
+    });
+}
+
+/// Parses contents in the /proc/net/dev format from any reader. With
+/// `skip_bad_lines` malformed lines are logged and ignored instead of failing
+/// the whole parse. `rx_column` and `tx_column` name the header columns read
+/// into the rx and tx counters, normally `bytes`
+pub fn parse_proc_net_dev_reader<R: BufRead>(
+    reader: R,
+    hide_zero_ifs: bool,
+    skip_bad_lines: bool,
+    filter: &InterfaceFilter,
+    rx_column: &str,
+    tx_column: &str,
+) -> anyhow::Result<StatisticsDb> {
+    let mut ret = StatisticsDb::new();
+    let mut lines = reader.lines().map_while(Result::ok);
+    let header = lines
+        .nth(1)
+        .ok_or_else(|| anyhow::anyhow!("Missing header lines"))?;
+    let columns = ProcNetDevColumns::from_header(&header, rx_column, tx_column)?;
+    for line in lines {
+        // The name is everything before the first colon. It may contain spaces
+        // and large counters follow the colon without any
+        let (ifname, rest) = match line.split_once(':') {
+            Some((name, rest)) if !name.trim().is_empty() => {
+                (name.trim().to_string(), rest)
+            }
+            _ if skip_bad_lines => {
+                log::warn!("Skipping line without interface name: '{line}'");
+                continue;
+            }
+            _ => return Err(anyhow::anyhow!("Missing interface name in '{line}'")),
+        };
+        if !filter.is_selected(&ifname) {
+            log::debug!("Interface '{ifname}' is filtered out. Ignoring");
+            continue;
+        }
+        let counters: Vec<&str> = rest.split_ascii_whitespace().collect();
+        let stat = match parse_proc_net_dev_counters(&counters, &columns) {
+            Ok(x) => x,
+            Err(e) if skip_bad_lines => {
+                log::warn!("Skipping malformed line '{line}': {e:#}");
+                continue;
+            }
+            Err(e) => {
+                return Err(e.context(format!("Bad line of interface '{ifname}'")))
+            }
+        };
+        if hide_zero_ifs && stat.rx.max(stat.tx) == 0 {
+            log::debug!("Interface '{ifname}' has zero statistics. Ignoring");
+            continue;
+        }
+        ret.insert(ifname, stat);
+    }
+    return Ok(ret);
+}
+
+/// Directory with one entry per interface on Linux
+pub const SYS_CLASS_NET_PATH: &str = "/sys/class/net";
+
+fn read_sysfs_counter(
+    statistics_dir: &std::path::Path,
+    name: &str,
+) -> anyhow::Result<u64> {
+    let path = statistics_dir.join(name);
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    return content
+        .trim()
+        .parse::<u64>()
+        .with_context(|| format!("Failed to parse {}", path.display()));
+}
+
+/// Reads the same counters as parse_proc_net_dev from
+/// `/sys/class/net/<iface>/statistics`
+pub fn parse_sysfs(
+    hide_zero_ifs: bool,
+    filter: &InterfaceFilter,
+) -> anyhow::Result<StatisticsDb> {
+    let mut ret = StatisticsDb::new();
+    let entries = std::fs::read_dir(SYS_CLASS_NET_PATH)
+        .with_context(|| format!("Failed to open {}", SYS_CLASS_NET_PATH))?;
+    for entry in entries {
+        let entry = entry.with_context(|| {
+            format!("Failed to read directory {}", SYS_CLASS_NET_PATH)
+        })?;
+        let ifname = entry.file_name().to_string_lossy().into_owned();
+        let statistics_dir = entry.path().join("statistics");
+        // Files like bonding_masters live next to the interfaces
+        if !statistics_dir.is_dir() {
+            log::debug!("'{ifname}' is not an interface. Ignoring");
+            continue;
+        }
+        if !filter.is_selected(&ifname) {
+            log::debug!("Interface '{ifname}' is filtered out. Ignoring");
+            continue;
+        }
+        let stat = DeviceStatistics {
+            rx: read_sysfs_counter(&statistics_dir, "rx_bytes")?,
+            tx: read_sysfs_counter(&statistics_dir, "tx_bytes")?,
+            rx_packets: read_sysfs_counter(&statistics_dir, "rx_packets")?,
+            tx_packets: read_sysfs_counter(&statistics_dir, "tx_packets")?,
+            rx_errors: read_sysfs_counter(&statistics_dir, "rx_errors")?,
+            tx_errors: read_sysfs_counter(&statistics_dir, "tx_errors")?,
+            rx_drops: read_sysfs_counter(&statistics_dir, "rx_dropped")?,
+            tx_drops: read_sysfs_counter(&statistics_dir, "tx_dropped")?,
+        };
+        if hide_zero_ifs && stat.rx.max(stat.tx) == 0 {
+            log::debug!("Interface '{ifname}' has zero statistics. Ignoring");
+            continue;
+        }
+        ret.insert(ifname, stat);
+    }
+    return Ok(ret);
+}
+
+/// Counter deltas of interfaces present in both snapshots. With `show_new`,
+/// interfaces missing from `b` are compared against a zero baseline
+pub fn subtract_device_rates(
+    a: &DeviceRates,
+    b: &DeviceRates,
+    counter_bits: CounterBits,
+    show_new: bool,
+) -> DeviceRates {
+    let mut ret = DeviceRates::new();
+    for (ifname, left_rate) in a.iter() {
+        if let Some(right_rate) = b.get(ifname) {
+            let result_stat =
+                left_rate.delta_since(right_rate, ifname, counter_bits);
+            ret.insert(ifname.clone(), result_stat);
+        } else if show_new {
+            log::debug!("New interface {ifname}, using a zero baseline");
+            ret.insert(ifname.clone(), *left_rate);
+        } else {
+            log::debug!("Skipping new interface {ifname} missing from the baseline");
+        }
+    }
+    return ret;
+}
+
+/// Divides `value` by `factor` until it is below it and returns the prefix of
+/// the last division, or an empty prefix for small and non-finite values
+pub fn get_human_value<'a>(
+    value: f64,
+    prefixes: &[&'a str],
+    factor: f64,
+) -> (f64, &'a str) {
+    let mut new_value = value;
+    let mut new_prefix = "";
+    if value.is_finite() {
+        for p in prefixes {
+            if new_value < factor {
+                break;
+            }
+            new_value /= factor;
+            new_prefix = p;
+        }
+    }
+    return (new_value, new_prefix);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclude_beats_include() {
+        let filter = InterfaceFilter {
+            names: Vec::new(),
+            no_loopback: false,
+            include: Some(regex::Regex::new("^eth").unwrap()),
+            exclude: Some(regex::Regex::new("^eth1$").unwrap()),
+        };
+        assert!(filter.is_selected("eth0"));
+        assert!(!filter.is_selected("eth1"));
+        assert!(!filter.is_selected("wlan0"));
+    }
+
+    #[test]
+    fn loopback_names_of_all_platforms() {
+        assert!(is_loopback_name("lo"));
+        assert!(is_loopback_name("lo0"));
+        assert!(is_loopback_name("Loopback Pseudo-Interface 1"));
+        assert!(!is_loopback_name("lowpan0"));
+        assert!(!is_loopback_name("eth0"));
+    }
+
+    #[test]
+    fn parses_padded_proc_net_dev_lines() {
+        let content = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+ my if:    100       2    0    0    0     0          0         0      200       3    0    0    0     0       0          0
+eth0:123456789012 7 1 2 0 0 0 0   987654321098   8    3    4    0     0       0          0
+";
+        let filter = InterfaceFilter {
+            names: Vec::new(),
+            no_loopback: false,
+            include: None,
+            exclude: None,
+        };
+        let db = parse_proc_net_dev_reader(
+            content.as_bytes(),
+            false,
+            false,
+            &filter,
+            "bytes",
+            "bytes",
+        )
+        .unwrap();
+        assert_eq!(db.kernel_order(), ["my if", "eth0"]);
+        let stat = db.devices["my if"];
+        assert_eq!(
+            (stat.rx, stat.tx, stat.rx_packets, stat.tx_packets),
+            (100, 200, 2, 3)
+        );
+        let stat = db.devices["eth0"];
+        assert_eq!((stat.rx, stat.tx), (123456789012, 987654321098));
+        assert_eq!((stat.rx_errors, stat.rx_drops), (1, 2));
+        assert_eq!((stat.tx_errors, stat.tx_drops), (3, 4));
+    }
+
+    #[test]
+    fn human_value_boundaries() {
+        let binary = ["Ki", "Mi", "Gi", "Ti"];
+        assert_eq!(get_human_value(1023_f64, &binary, 1024_f64), (1023_f64, ""));
+        assert_eq!(get_human_value(1024_f64, &binary, 1024_f64), (1_f64, "Ki"));
+        assert_eq!(
+            get_human_value(1025_f64, &binary, 1024_f64),
+            (1025_f64 / 1024_f64, "Ki")
+        );
+        let decimal = ["k", "M", "G", "T"];
+        assert_eq!(get_human_value(999_f64, &decimal, 1000_f64), (999_f64, ""));
+        assert_eq!(get_human_value(1000_f64, &decimal, 1000_f64), (1_f64, "k"));
+        assert_eq!(get_human_value(1001_f64, &decimal, 1000_f64), (1.001, "k"));
+        // Values beyond the largest prefix stay scaled by it
+        assert_eq!(get_human_value(5e15, &decimal, 1000_f64), (5000_f64, "T"));
+        let (value, prefix) = get_human_value(f64::INFINITY, &decimal, 1000_f64);
+        assert_eq!((value.is_infinite(), prefix), (true, ""));
+    }
+
+    #[test]
+    fn migrates_unversioned_db() {
+        let json = r#"{"timestamp":"2023-01-01T00:00:00Z","devices":{"eth0":{"rx":1,"tx":2}}}"#;
+        let db = serde_json::from_str::<StatisticsDb>(json)
+            .unwrap()
+            .check_version()
+            .unwrap();
+        assert_eq!(db.version, DB_VERSION);
+        let stat = db.devices["eth0"];
+        assert_eq!((stat.rx, stat.tx), (1, 2));
+        assert_eq!((stat.rx_packets, stat.tx_drops), (0, 0));
+    }
+
+    #[test]
+    fn rejects_newer_db() {
+        let json =
+            r#"{"timestamp":"2023-01-01T00:00:00Z","devices":{},"version":2}"#;
+        let db = serde_json::from_str::<StatisticsDb>(json).unwrap();
+        assert!(db.check_version().is_err());
+    }
+}
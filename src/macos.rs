@@ -0,0 +1,115 @@
+//! Interface counters from getifaddrs link level entries
+
+use super::{DeviceStatistics, InterfaceFilter, StatisticsDb};
+
+const AF_LINK: u8 = 18;
+
+#[repr(C)]
+#[allow(dead_code)]
+struct SockAddr {
+    sa_len: u8,
+    sa_family: u8,
+    sa_data: [u8; 14],
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct IfAddrs {
+    ifa_next: *mut IfAddrs,
+    ifa_name: *const std::ffi::c_char,
+    ifa_flags: u32,
+    ifa_addr: *mut SockAddr,
+    ifa_netmask: *mut SockAddr,
+    ifa_dstaddr: *mut SockAddr,
+    ifa_data: *mut std::ffi::c_void,
+}
+
+/// struct if_data from net/if_var.h. Counters are 32 bits wide and wrap
+/// often, so --counter-bits 32 is recommended
+#[repr(C)]
+#[allow(dead_code)]
+struct IfData {
+    ifi_type: u8,
+    ifi_typelen: u8,
+    ifi_physical: u8,
+    ifi_addrlen: u8,
+    ifi_hdrlen: u8,
+    ifi_recvquota: u8,
+    ifi_xmitquota: u8,
+    ifi_unused1: u8,
+    ifi_mtu: u32,
+    ifi_metric: u32,
+    ifi_baudrate: u32,
+    ifi_ipackets: u32,
+    ifi_ierrors: u32,
+    ifi_opackets: u32,
+    ifi_oerrors: u32,
+    ifi_collisions: u32,
+    ifi_ibytes: u32,
+    ifi_obytes: u32,
+    ifi_imcasts: u32,
+    ifi_omcasts: u32,
+    ifi_iqdrops: u32,
+    ifi_noproto: u32,
+    ifi_recvtiming: u32,
+    ifi_xmittiming: u32,
+    ifi_lastchange: [u32; 2],
+    ifi_unused2: u32,
+    ifi_hwassist: u32,
+    ifi_reserved1: u32,
+    ifi_reserved2: u32,
+}
+
+extern "C" {
+    fn getifaddrs(ifap: *mut *mut IfAddrs) -> std::ffi::c_int;
+    fn freeifaddrs(ifa: *mut IfAddrs);
+}
+
+/// Reads counters of every interface from its AF_LINK entry
+pub(crate) fn parse_ifaddrs(
+    hide_zero_ifs: bool,
+    filter: &InterfaceFilter,
+) -> anyhow::Result<StatisticsDb> {
+    let mut head: *mut IfAddrs = std::ptr::null_mut();
+    if unsafe { getifaddrs(&mut head) } != 0 {
+        return Err(anyhow::anyhow!(
+            "getifaddrs failed: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    let mut ret = StatisticsDb::new();
+    let mut cursor = head;
+    while let Some(entry) = unsafe { cursor.as_ref() } {
+        cursor = entry.ifa_next;
+        let is_link = unsafe { entry.ifa_addr.as_ref() }
+            .is_some_and(|x| x.sa_family == AF_LINK);
+        let data = match unsafe { (entry.ifa_data as *const IfData).as_ref() } {
+            Some(x) if is_link => x,
+            _ => continue,
+        };
+        let ifname = unsafe { std::ffi::CStr::from_ptr(entry.ifa_name) }
+            .to_string_lossy()
+            .into_owned();
+        if !filter.is_selected(&ifname) {
+            log::debug!("Interface '{ifname}' is filtered out. Ignoring");
+            continue;
+        }
+        let stat = DeviceStatistics {
+            rx: data.ifi_ibytes.into(),
+            tx: data.ifi_obytes.into(),
+            rx_packets: data.ifi_ipackets.into(),
+            tx_packets: data.ifi_opackets.into(),
+            rx_errors: data.ifi_ierrors.into(),
+            tx_errors: data.ifi_oerrors.into(),
+            rx_drops: data.ifi_iqdrops.into(),
+            tx_drops: 0,
+        };
+        if hide_zero_ifs && stat.rx.max(stat.tx) == 0 {
+            log::debug!("Interface '{ifname}' has zero statistics. Ignoring");
+            continue;
+        }
+        ret.insert(ifname, stat);
+    }
+    unsafe { freeifaddrs(head) };
+    return Ok(ret);
+}
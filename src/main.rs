@@ -1,257 +1,3894 @@
+#![allow(clippy::needless_return)]
+
 use anyhow::Context;
+use ifstat_rs::{
+    get_human_value, subtract_device_rates, CounterBits, DeviceRates,
+    DeviceStatistics, InterfaceFilter, StatisticsDb, PROC_NET_DEV_PATH,
+    SYS_CLASS_NET_PATH,
+};
+#[cfg(not(any(windows, target_os = "macos")))]
+use ifstat_rs::{parse_proc_net_dev, parse_sysfs};
 use std::io::{BufRead, Write};
 
-#[derive(Copy, Clone, serde::Serialize, serde::Deserialize)]
-struct DeviceStatistics {
-    rx: u64,
-    tx: u64,
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(target_os = "macos")]
+const DEFAULT_COUNTER_BITS: CounterBitsArg = CounterBitsArg::Bits32;
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_COUNTER_BITS: CounterBitsArg = CounterBitsArg::Bits64;
+
+/// Display names of interfaces, keyed by the real interface name
+type InterfaceAliases = std::collections::BTreeMap<String, String>;
+
+fn get_display_name<'a>(ifname: &'a str, aliases: &'a InterfaceAliases) -> &'a str {
+    return aliases.get(ifname).map(|x| x.as_str()).unwrap_or(ifname);
 }
-impl std::ops::SubAssign for DeviceStatistics {
-    fn sub_assign(&mut self, rhs: Self) {
-        self.rx -= rhs.rx;
-        self.tx -= rhs.tx;
+
+/// Bucket of an interface, the first capture group of `group`. Interfaces
+/// that do not match are their own bucket
+fn get_group_name<'a>(ifname: &'a str, group: &regex::Regex) -> &'a str {
+    return match group.captures(ifname).and_then(|x| x.get(1)) {
+        Some(x) => x.as_str(),
+        None => ifname,
+    };
+}
+
+/// Sums interfaces into buckets named by get_group_name
+fn group_device_rates(rates: &DeviceRates, group: &regex::Regex) -> DeviceRates {
+    let mut ret = DeviceRates::new();
+    for (ifname, stat) in rates.iter() {
+        *ret.entry(get_group_name(ifname, group).to_string())
+            .or_default() += *stat;
     }
+    return ret;
 }
-impl std::ops::Sub<DeviceStatistics> for DeviceStatistics {
-    type Output = Self;
-    fn sub(self, rhs: DeviceStatistics) -> Self::Output {
-        let mut tmp = self;
-        tmp -= rhs;
-        return tmp;
+
+/// Grouped counters of `db`. Buckets are ordered by their first interface in
+/// the kernel order
+fn group_statistics_db(db: &StatisticsDb, group: &regex::Regex) -> StatisticsDb {
+    let devices = group_device_rates(&db.devices, group);
+    let mut ret = StatisticsDb::new();
+    ret.timestamp = db.timestamp;
+    ret.version = db.version;
+    for ifname in db.kernel_order() {
+        let bucket = get_group_name(ifname, group);
+        if let Some(stat) = devices.get(bucket) {
+            ret.insert(bucket.to_string(), *stat);
+        }
+    }
+    ret.devices = devices;
+    return ret;
+}
+
+/// Logs interfaces present in only one of two consecutive snapshots
+fn log_interface_changes(previous: &DeviceRates, current: &DeviceRates) {
+    for ifname in current.keys().filter(|x| !previous.contains_key(*x)) {
+        log::info!("{ifname} appeared");
+    }
+    for ifname in previous.keys().filter(|x| !current.contains_key(*x)) {
+        log::info!("{ifname} disappeared");
+    }
+}
+
+/// Interfaces whose receive plus transmit rate is at least `min_rate` bytes
+/// per second. Without an interval rates are unknown and all are kept
+fn filter_min_rate(diff: &DeviceRates, seconds: f64, min_rate: f64) -> DeviceRates {
+    return diff
+        .iter()
+        .filter(|(_, x)| {
+            return seconds <= 0_f64 || (x.rx + x.tx) as f64 / seconds >= min_rate;
+        })
+        .map(|(k, v)| (k.clone(), *v))
+        .collect();
+}
+
+/// Writes the db to a temporary file next to `path` and renames it into
+/// place, so an interrupted write never leaves a truncated history file
+fn dump_stat_db(
+    path: &str,
+    db: &StatisticsDb,
+    format: DbFormat,
+) -> anyhow::Result<()> {
+    let tmp_path = format!("{}.tmp.{}", path, std::process::id());
+    let compress = path.ends_with(".gz");
+    let result = write_stat_db(&tmp_path, db, compress, format).and_then(|_| {
+        return std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to rename {} to {}", tmp_path, path));
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
     }
+    return result;
 }
 
-type DeviceRates = std::collections::BTreeMap<String, DeviceStatistics>;
+fn write_stat_db(
+    path: &str,
+    db: &StatisticsDb,
+    compress: bool,
+    format: DbFormat,
+) -> anyhow::Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to open {}", path))?;
+    let data = match format {
+        DbFormat::Json => serde_json::to_vec(&db).context("Serialization failed")?,
+        DbFormat::JsonPretty => {
+            serde_json::to_vec_pretty(&db).context("Serialization failed")?
+        }
+        DbFormat::Bincode => {
+            bincode::serialize(db).context("Serialization failed")?
+        }
+    };
+    let mut buf_writer = std::io::BufWriter::new(&file);
+    if compress {
+        let mut encoder = flate2::write::GzEncoder::new(
+            &mut buf_writer,
+            flate2::Compression::default(),
+        );
+        encoder.write_all(&data).context("Write failed")?;
+        encoder.finish().context("Compression failed")?;
+    } else {
+        buf_writer.write_all(&data).context("Write failed")?;
+    }
+    buf_writer.flush().context("Flush failed")?;
+    drop(buf_writer);
+    file.sync_all().context("Sync failed")?;
+    return Ok(());
+}
 
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
-struct StatisticsDb {
+/// Layout of bincode files written before the db was versioned
+#[derive(serde::Deserialize)]
+struct UnversionedDb {
     timestamp: chrono::DateTime<chrono::Utc>,
     devices: DeviceRates,
 }
-impl StatisticsDb {
-    fn new() -> Self {
-        let timestamp = chrono::Utc::now();
-        let devices = DeviceRates::new();
-        return Self { timestamp, devices };
+
+fn decode_bincode_db(data: &[u8]) -> anyhow::Result<StatisticsDb> {
+    let e = match bincode::deserialize::<StatisticsDb>(data) {
+        Ok(x) => return Ok(x),
+        Err(e) => e,
+    };
+    // Unversioned files end right after the devices
+    return match bincode::deserialize::<UnversionedDb>(data) {
+        Ok(x) => {
+            let mut ret = StatisticsDb::new();
+            ret.timestamp = x.timestamp;
+            ret.devices = x.devices;
+            ret.version = 0;
+            Ok(ret)
+        }
+        Err(_) => Err(e.into()),
+    };
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Reads a history file, decompressing it when it starts with the gzip
+/// magic regardless of the file name. JSON is told apart from bincode by
+/// its opening brace
+fn parse_stat_db(path: &str) -> anyhow::Result<StatisticsDb> {
+    let mut data =
+        std::fs::read(path).with_context(|| format!("Failed to open {}", path))?;
+    if data.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        std::io::Read::read_to_end(
+            &mut flate2::read::GzDecoder::new(data.as_slice()),
+            &mut decompressed,
+        )
+        .with_context(|| format!("Failed to decompress {}", path))?;
+        data = decompressed;
     }
+    let is_json = data
+        .iter()
+        .find(|x| !x.is_ascii_whitespace())
+        .is_some_and(|x| *x == b'{');
+    let ret: StatisticsDb = if is_json {
+        serde_json::from_slice(&data).context("Failed to parse db")?
+    } else {
+        decode_bincode_db(&data).context("Failed to parse bincode db")?
+    };
+    return ret.check_version();
 }
 
-const PROC_NET_DEV_PATH: &str = "/proc/net/dev";
+fn is_file_exist(path: &str) -> bool {
+    return std::path::Path::new(path).is_file();
+}
 
-fn parse_proc_net_dev(hide_zero_ifs: bool) -> anyhow::Result<StatisticsDb> {
-    let mut ret = StatisticsDb::new();
-    let buf_reader = std::io::BufReader::new(
-        std::fs::File::open(PROC_NET_DEV_PATH)
-            .with_context(|| format!("Failed to open {}", PROC_NET_DEV_PATH))?,
-    );
-    for line in buf_reader.lines().skip(2).filter_map(|x| x.ok()) {
-        let mut split = line.split_ascii_whitespace();
-        let ifname = match split.next() {
-            Some(x) => x.trim_end_matches(':').to_string(),
-            None => return Err(anyhow::anyhow!("Missing interface name")),
-        };
-        let rx = match split.next() {
-            Some(x) => x.parse::<u64>().context("Failed to parse rx bytes")?,
-            None => return Err(anyhow::anyhow!("Missing rx bytes")),
-        };
-        let tx = match split.skip(7).next() {
-            Some(x) => x.parse::<u64>().context("Failed to parse tx bytes")?,
-            None => return Err(anyhow::anyhow!("Missing tx bytes")),
-        };
-        if hide_zero_ifs && rx.max(tx) == 0 {
-            log::debug!("Interface '{ifname}' has zero statistics. Ignoring");
-            continue;
-        }
-        ret.devices.insert(ifname, DeviceStatistics { rx, tx });
+fn get_rate_color(bytes_per_second: f64) -> &'static str {
+    const MIB: f64 = 1024_f64 * 1024_f64;
+    if bytes_per_second < MIB {
+        return "\x1b[32m";
+    } else if bytes_per_second < 100_f64 * MIB {
+        return "\x1b[33m";
     }
-    return Ok(ret);
+    return "\x1b[31m";
 }
 
-fn subtract_device_rates(a: &DeviceRates, b: &DeviceRates) -> DeviceRates {
-    let mut ret = DeviceRates::new();
-    for (ifname, left_rate) in a.iter() {
-        if let Some(right_rate) = b.get(ifname) {
-            let result_stat = *left_rate - *right_rate;
-            ret.insert(ifname.clone(), result_stat);
-        } else {
-            continue;
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum PrefixSystem {
+    /// IEC Ki, Mi, ... with factor 1024
+    #[value(name = "iec")]
+    Binary,
+    /// SI K, M, ... with factor 1000
+    #[value(name = "si")]
+    Decimal,
+}
+impl PrefixSystem {
+    fn get_prefixes(self, raw: bool) -> (&'static [&'static str], f64) {
+        if raw {
+            return (&[], 1_f64);
         }
+        return match self {
+            PrefixSystem::Binary => (&["Ki", "Mi", "Gi", "Ti"], 1024_f64),
+            PrefixSystem::Decimal => (&["K", "M", "G", "T"], 1000_f64),
+        };
     }
-    return ret;
 }
 
-fn dump_stat_db(path: &str, db: &StatisticsDb) -> anyhow::Result<()> {
-    let mut buf_writer = std::io::BufWriter::new(
-        std::fs::File::create(path)
-            .with_context(|| format!("Failed to open {}", path))?,
-    );
-    serde_json::to_writer(&mut buf_writer, &db).context("Serialization failed")?;
-    buf_writer.flush().context("Flush failed")?;
+/// Shown instead of a rate when there is no baseline, i.e. the interval is
+/// zero and rates are infinite or NaN
+const NO_RATE_PLACEHOLDER: &str = "n/a";
+
+fn format_bytes_and_bits(value: f64, options: &TableOptions) -> String {
+    if !value.is_finite() {
+        return NO_RATE_PLACEHOLDER.to_string();
+    }
+    let (byte_prefixes, byte_factor) =
+        options.byte_prefixes.get_prefixes(options.raw);
+    let (pretty_bytes, bytes_prefix) =
+        get_human_value(value, byte_prefixes, byte_factor);
+    let (bit_prefixes, bit_factor) = options.bit_prefixes.get_prefixes(options.raw);
+    let (pretty_bits, bits_prefix) =
+        get_human_value(value * 8_f64, bit_prefixes, bit_factor);
+    let precision = options.precision;
+    let bytes = format!("{:.precision$} {}B/s", pretty_bytes, bytes_prefix);
+    let bits = format!("{:.precision$} {}bit/s", pretty_bits, bits_prefix);
+    return match options.units {
+        Units::Bytes => bytes,
+        Units::Bits => bits,
+        Units::Both => format!("{} ({})", bytes, bits),
+    };
+}
+
+/// Humanized amount of bytes without the per-second suffix
+fn format_bytes(value: f64, options: &TableOptions) -> String {
+    let (prefixes, factor) = options.byte_prefixes.get_prefixes(options.raw);
+    let (pretty_value, prefix) = get_human_value(value, prefixes, factor);
+    let precision = options.precision;
+    return format!("{:.precision$} {}B", pretty_value, prefix);
+}
+
+fn pretty_print_bytes_and_bites(
+    out: &mut dyn Write,
+    value: f64,
+    width: usize,
+    options: &TableOptions,
+) -> std::io::Result<()> {
+    let combined = format_bytes_and_bits(value, options);
+    if options.color && value.is_finite() {
+        let padded = format!("{:>width$}", combined);
+        write!(out, " {}{}\x1b[0m", get_rate_color(value), padded)?;
+    } else {
+        write!(out, " {:>width$}", combined)?;
+    }
     return Ok(());
 }
 
-fn parse_stat_db(path: &str) -> Result<StatisticsDb, Box<dyn std::error::Error>> {
-    let buf_reader = std::io::BufReader::new(
-        std::fs::File::open(path)
-            .with_context(|| format!("Failed to open {}", path))?,
-    );
-    let ret = serde_json::from_reader(buf_reader).context("Failed to parse db")?;
-    return Ok(ret);
+fn make_repeated_string(c: char, n: usize) -> String {
+    return std::iter::repeat_n(c, n).collect();
 }
 
-fn is_file_exist(path: &str) -> bool {
-    return std::path::Path::new(path).is_file();
+/// Cuts `s` to `width` characters, the last of them being an ellipsis
+fn truncate_with_ellipsis(s: &str, width: usize) -> std::borrow::Cow<'_, str> {
+    if s.chars().count() <= width {
+        return s.into();
+    }
+    let mut ret: String = s.chars().take(width.saturating_sub(1)).collect();
+    if width > 0 {
+        ret.push('…');
+    }
+    return ret.into();
 }
 
-fn get_human_value<'a>(
-    value: f64,
-    prefixes: &[&'a str],
-    factor: f64,
-) -> (f64, &'a str) {
-    let mut new_value = value;
-    let mut new_prefix = "";
-    if value.is_finite() {
-        for p in prefixes {
-            if new_value <= factor {
-                break;
-            }
-            new_value /= factor;
-            new_prefix = p;
+/// Whether interfaces are listed in the natural order of the sort key, which is
+/// alphabetical for names and highest first for statistics, or the opposite
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum SortDirection {
+    Natural,
+    Reversed,
+}
+
+/// Interfaces of `speeds` in display order. Statistic keys sort by the rates
+/// being shown rather than by the cumulative counters, so --top finds the
+/// busiest interfaces. `db` provides the kernel order
+fn get_sorted_ifs(
+    speeds: &DeviceSpeeds,
+    db: &StatisticsDb,
+    sort_key: SortKey,
+    direction: SortDirection,
+    aliases: &InterfaceAliases,
+) -> Vec<String> {
+    let mut v: Vec<(String, DeviceSpeed)> =
+        speeds.iter().map(|(k, v)| (k.clone(), *v)).collect();
+    let get_key = |v: &DeviceSpeed| -> f64 {
+        let key = match sort_key {
+            SortKey::Name | SortKey::Kernel => 0_f64,
+            SortKey::Total => v.rx + v.tx,
+            SortKey::Rx => v.rx,
+            SortKey::Tx => v.tx,
+        };
+        // Idle interfaces without a baseline have NaN rates
+        return if key.is_nan() { 0_f64 } else { key };
+    };
+    // Larger rates come first. Ties are always broken alphabetically so
+    // the order is stable between runs
+    v.sort_by(|(a_name, a), (b_name, b)| {
+        let by_name =
+            get_display_name(a_name, aliases).cmp(get_display_name(b_name, aliases));
+        if sort_key == SortKey::Name {
+            return match direction {
+                SortDirection::Natural => by_name,
+                SortDirection::Reversed => by_name.reverse(),
+            };
         }
+        if sort_key == SortKey::Kernel {
+            // Interfaces missing from the kernel order go last
+            let get_index = |ifname: &String| -> usize {
+                return db
+                    .kernel_order()
+                    .iter()
+                    .position(|x| x == ifname)
+                    .unwrap_or(usize::MAX);
+            };
+            let by_index = get_index(a_name).cmp(&get_index(b_name)).then(by_name);
+            return match direction {
+                SortDirection::Natural => by_index,
+                SortDirection::Reversed => by_index.reverse(),
+            };
+        }
+        let by_key = match direction {
+            SortDirection::Natural => get_key(b).total_cmp(&get_key(a)),
+            SortDirection::Reversed => get_key(a).total_cmp(&get_key(b)),
+        };
+        return by_key.then(by_name);
+    });
+    return v.iter().map(|(k, _)| k).cloned().collect();
+}
+
+/// Humanized rate of a plain counter with decimal prefixes like `1.20 Kpkt/s`
+fn format_per_second(value: f64, unit: &str, options: &TableOptions) -> String {
+    if !value.is_finite() {
+        return NO_RATE_PLACEHOLDER.to_string();
     }
-    return (new_value, new_prefix);
+    let decimal_prefixes: &[&str] = if options.raw {
+        &[]
+    } else {
+        &["K", "M", "G", "T"]
+    };
+    let (pretty_value, prefix) = get_human_value(value, decimal_prefixes, 1000_f64);
+    let precision = options.precision;
+    return format!("{:.precision$} {}{}/s", pretty_value, prefix, unit);
 }
 
-fn pretty_print_bytes_and_bites(value: f64, width: usize) {
-    let binary_prefixes = ["Ki", "Mi", "Gi", "Ti"];
-    let (pretty_binary_bytes, bytes_prefix) =
-        get_human_value(value, &binary_prefixes, 1024_f64);
-    let decimal_prefixes = ["K", "M", "G", "T"];
-    let (pretty_decimal_bits, bits_prefix) =
-        get_human_value(value * 8_f64, &decimal_prefixes, 1000_f64);
-    let precision = 2;
-    let combined = format!(
-        "{:.precision$} {}B/s ({:.precision$} {}bit/s)",
-        pretty_binary_bytes, bytes_prefix, pretty_decimal_bits, bits_prefix
-    );
-    print!(" {:>width$}", combined);
+fn pretty_print_per_second(
+    out: &mut dyn Write,
+    value: f64,
+    unit: &str,
+    width: usize,
+    options: &TableOptions,
+) -> std::io::Result<()> {
+    write!(out, " {:>width$}", format_per_second(value, unit, options))?;
+    return Ok(());
 }
 
-fn make_repeated_string(c: char, n: usize) -> String {
-    return std::iter::repeat(c).take(n).collect();
+/// Additional table column showing a per-second rate of a plain counter
+struct CounterColumn {
+    header: &'static str,
+    unit: &'static str,
+    get: fn(&DeviceSpeed) -> f64,
 }
 
-fn get_sorted_ifs(db: &StatisticsDb, sort_by_stat: bool) -> Vec<String> {
-    let mut v: Vec<(String, DeviceStatistics)> = db
-        .devices
-        .iter()
-        .map(|(k, v)| (k.clone(), v.clone()))
-        .collect();
-    if sort_by_stat {
-        v.sort_by_key(|(_, v)| v.rx + v.tx);
-        v.reverse();
-    } else {
-        v.sort_by_key(|(k, _)| k.clone());
+fn get_counter_columns(options: &TableOptions) -> Vec<CounterColumn> {
+    let mut ret = Vec::new();
+    if options.show_packets {
+        ret.push(CounterColumn {
+            header: "RX packets",
+            unit: "pkt",
+            get: |x| x.rx_packets,
+        });
+        ret.push(CounterColumn {
+            header: "TX packets",
+            unit: "pkt",
+            get: |x| x.tx_packets,
+        });
     }
-    return v.iter().map(|(k, _)| k).cloned().collect();
+    if options.show_errors {
+        ret.push(CounterColumn {
+            header: "RX errors",
+            unit: "err",
+            get: |x| x.rx_errors,
+        });
+        ret.push(CounterColumn {
+            header: "TX errors",
+            unit: "err",
+            get: |x| x.tx_errors,
+        });
+        ret.push(CounterColumn {
+            header: "RX drops",
+            unit: "drop",
+            get: |x| x.rx_drops,
+        });
+        ret.push(CounterColumn {
+            header: "TX drops",
+            unit: "drop",
+            get: |x| x.tx_drops,
+        });
+    }
+    return ret;
+}
+
+/// Options controlling the layout of the human-readable table
+struct TableOptions {
+    hide_zero_values: bool,
+    sort_key: SortKey,
+    sort_direction: SortDirection,
+    top: Option<usize>,
+    show_packets: bool,
+    show_errors: bool,
+    show_total: bool,
+    totals_only: bool,
+    flat: bool,
+    show_combined: bool,
+    show_utilization: bool,
+    show_total_bytes: bool,
+    show_delta: bool,
+    show_avg_pktsize: bool,
+    bond_detail: bool,
+    trend: bool,
+    trend_threshold: f64,
+    peak_hold: bool,
+    color: bool,
+    units: Units,
+    byte_prefixes: PrefixSystem,
+    bit_prefixes: PrefixSystem,
+    column_width: Option<usize>,
+    name_width: Option<usize>,
+    raw: bool,
+    precision: usize,
+    no_header: bool,
+    aliases: InterfaceAliases,
+}
+
+struct TableLayout {
+    ifname_width: usize,
+    number_width: usize,
+    counter_width: usize,
+    counter_columns: Vec<CounterColumn>,
+    total_bytes_width: usize,
+    delta_width: usize,
+    hold_width: usize,
+    pktsize_width: usize,
+    util_width: usize,
+}
+
+/// Zero-length intervals turn zero counters into NaN which is still shown as
+/// an empty cell
+fn is_zero_rate(value: f64) -> bool {
+    return value == 0_f64 || value.is_nan();
+}
+
+/// Negotiated link speed in bits per second from
+/// /sys/class/net/<iface>/speed. Virtual devices have no such file or report -1
+fn read_link_speed(ifname: &str) -> Option<f64> {
+    let path = std::path::Path::new(SYS_CLASS_NET_PATH)
+        .join(ifname)
+        .join("speed");
+    let content = std::fs::read_to_string(path).ok()?;
+    let mbits = content.trim().parse::<i64>().ok()?;
+    if mbits <= 0 {
+        return None;
+    }
+    return Some(mbits as f64 * 1e6);
+}
+
+/// Mean size of the packets behind a byte rate and a packet rate. Both rates
+/// share the interval, so it is the byte delta over the packet delta. None
+/// without packets
+fn get_avg_packet_size(bytes: f64, packets: f64) -> Option<f64> {
+    if packets == 0_f64 || !packets.is_finite() || !bytes.is_finite() {
+        return None;
+    }
+    return Some(bytes / packets);
+}
+
+/// Arrow comparing a rate with the one of the previous sample. Changes up to
+/// the trend threshold count as steady. Blank without a previous rate
+fn get_trend_marker(
+    value: f64,
+    previous: Option<f64>,
+    options: &TableOptions,
+) -> &'static str {
+    let previous = match previous {
+        Some(x) if x.is_finite() && value.is_finite() => x,
+        _ => return " ",
+    };
+    if value > previous + options.trend_threshold {
+        return "↑";
+    } else if value < previous - options.trend_threshold {
+        return "↓";
+    }
+    return "→";
+}
+
+#[allow(clippy::too_many_arguments)]
+fn pretty_print_row(
+    out: &mut dyn Write,
+    label: &str,
+    speed: &DeviceSpeed,
+    cumulative: &DeviceStatistics,
+    delta: Option<&DeviceStatistics>,
+    previous: Option<&DeviceSpeed>,
+    held: Option<&DeviceSpeed>,
+    link_speed: Option<f64>,
+    layout: &TableLayout,
+    options: &TableOptions,
+) -> std::io::Result<()> {
+    let ifname_width = layout.ifname_width;
+    let label = truncate_with_ellipsis(label, ifname_width);
+    write!(out, "{:>ifname_width$}", label)?;
+    let combined = options.show_combined.then_some(speed.rx + speed.tx);
+    let previous_rates = [previous.map(|x| x.rx), previous.map(|x| x.tx)];
+    for (i, col) in [speed.rx, speed.tx].into_iter().chain(combined).enumerate() {
+        if options.hide_zero_values && is_zero_rate(col) {
+            write!(out, " {}", make_repeated_string(' ', layout.number_width))?;
+        } else {
+            pretty_print_bytes_and_bites(out, col, layout.number_width, options)?;
+        }
+        if options.trend && i < previous_rates.len() {
+            let marker = get_trend_marker(col, previous_rates[i], options);
+            write!(out, " {}", marker)?;
+        }
+    }
+    if options.peak_hold {
+        let hold_width = layout.hold_width;
+        match held {
+            Some(held) => {
+                for col in [held.rx, held.tx] {
+                    let formatted = format_bytes_and_bits(col, options);
+                    write!(out, " {:>hold_width$}", formatted)?;
+                }
+            }
+            None => write!(out, " {:>hold_width$} {:>hold_width$}", "-", "-")?,
+        }
+    }
+    for column in layout.counter_columns.iter() {
+        let col = (column.get)(speed);
+        if options.hide_zero_values && is_zero_rate(col) {
+            write!(out, " {}", make_repeated_string(' ', layout.counter_width))?;
+        } else {
+            pretty_print_per_second(
+                out,
+                col,
+                column.unit,
+                layout.counter_width,
+                options,
+            )?;
+        }
+    }
+    if options.show_delta {
+        let delta_width = layout.delta_width;
+        match delta {
+            Some(delta) => {
+                for col in [delta.rx, delta.tx] {
+                    let formatted = format_bytes(col as f64, options);
+                    write!(out, " {:>delta_width$}", formatted)?;
+                }
+            }
+            None => write!(out, " {:>delta_width$} {:>delta_width$}", "-", "-")?,
+        }
+    }
+    if options.show_avg_pktsize {
+        let pktsize_width = layout.pktsize_width;
+        for (bytes, packets) in
+            [(speed.rx, speed.rx_packets), (speed.tx, speed.tx_packets)]
+        {
+            match get_avg_packet_size(bytes, packets) {
+                Some(x) => {
+                    let precision = options.precision;
+                    let formatted = format!("{:.precision$} B", x);
+                    write!(out, " {:>pktsize_width$}", formatted)?;
+                }
+                None => write!(out, " {:>pktsize_width$}", "-")?,
+            }
+        }
+    }
+    if options.show_total_bytes {
+        let total_bytes_width = layout.total_bytes_width;
+        for col in [cumulative.rx, cumulative.tx] {
+            let formatted = format_bytes(col as f64, options);
+            write!(out, " {:>total_bytes_width$}", formatted)?;
+        }
+    }
+    if options.show_utilization {
+        let util_width = layout.util_width;
+        for col in [speed.rx, speed.tx] {
+            match link_speed {
+                _ if !col.is_finite() => {
+                    write!(out, " {:>util_width$}", NO_RATE_PLACEHOLDER)?
+                }
+                Some(x) => {
+                    let precision = options.precision;
+                    let percent = col * 8_f64 / x * 100_f64;
+                    write!(
+                        out,
+                        " {:>util_width$}",
+                        format!("{:.precision$} %", percent)
+                    )?;
+                }
+                None => write!(out, " {:>util_width$}", "-")?,
+            }
+        }
+    }
+    writeln!(out)?;
+    return Ok(());
 }
 
+/// Rate table of `speeds` with the byte counts of `diff`, followed by the
+/// interval they were measured over
+#[allow(clippy::too_many_arguments)]
 fn pretty_print_devices_speed(
+    out: &mut dyn Write,
+    speeds: &DeviceSpeeds,
     diff: &DeviceRates,
     db: &StatisticsDb,
     seconds: f64,
-    hide_zero_values: bool,
-    sort_by_stat: bool,
-) {
-    let number_width = 30;
-    let ifname_width = diff.keys().map(|x| x.len()).max().unwrap_or(0).max(10);
-    println!(
-        "{:>ifname_width$} {:^number_width$} {:^number_width$}",
-        "Interface", "Receive", "Transmit"
-    );
-    let sorted_ifs = get_sorted_ifs(&db, sort_by_stat);
-    for ifname in sorted_ifs {
-        let stat = match diff.get(&ifname) {
-            Some(x) => x,
-            None => continue,
+    previous: Option<&DeviceSpeeds>,
+    held: Option<&DeviceSpeeds>,
+    options: &TableOptions,
+) -> std::io::Result<()> {
+    pretty_print_speed_table(out, speeds, Some(diff), previous, held, db, options)?;
+    if !options.no_header
+        && !options.totals_only
+        && !options.flat
+        && seconds > 0_f64
+        && !diff.is_empty()
+    {
+        let precision = options.precision;
+        writeln!(out, "Interval: {:.precision$} s", seconds)?;
+    }
+    return Ok(());
+}
+
+fn pretty_print_header(
+    out: &mut dyn Write,
+    layout: &TableLayout,
+    options: &TableOptions,
+) -> std::io::Result<()> {
+    let ifname_width = layout.ifname_width;
+    let number_width = layout.number_width;
+    let counter_width = layout.counter_width;
+    // Trend markers take a space and an arrow after each rate
+    let rate_width = number_width + if options.trend { 2 } else { 0 };
+    write!(
+        out,
+        "{:>ifname_width$} {:^rate_width$} {:^rate_width$}",
+        truncate_with_ellipsis("Interface", ifname_width),
+        "Receive",
+        "Transmit"
+    )?;
+    if options.show_combined {
+        write!(out, " {:^number_width$}", "Combined")?;
+    }
+    if options.peak_hold {
+        let hold_width = layout.hold_width;
+        write!(
+            out,
+            " {:^hold_width$} {:^hold_width$}",
+            "RX peak", "TX peak"
+        )?;
+    }
+    for column in layout.counter_columns.iter() {
+        write!(out, " {:^counter_width$}", column.header)?;
+    }
+    if options.show_delta {
+        let delta_width = layout.delta_width;
+        write!(
+            out,
+            " {:^delta_width$} {:^delta_width$}",
+            "RX delta", "TX delta"
+        )?;
+    }
+    if options.show_avg_pktsize {
+        let pktsize_width = layout.pktsize_width;
+        write!(
+            out,
+            " {:^pktsize_width$} {:^pktsize_width$}",
+            "RX pkt size", "TX pkt size"
+        )?;
+    }
+    if options.show_total_bytes {
+        let total_bytes_width = layout.total_bytes_width;
+        write!(
+            out,
+            " {:^total_bytes_width$} {:^total_bytes_width$}",
+            "RX total", "TX total"
+        )?;
+    }
+    if options.show_utilization {
+        let util_width = layout.util_width;
+        write!(
+            out,
+            " {:^util_width$} {:^util_width$}",
+            "RX util", "TX util"
+        )?;
+    }
+    writeln!(out)?;
+    return Ok(());
+}
+
+/// Rates, cumulative counters and deltas of a row summing several interfaces
+type SummedRow = (DeviceSpeed, DeviceStatistics, Option<DeviceStatistics>);
+
+/// Slaves of bond masters, keyed by the master
+type BondSlaves = std::collections::BTreeMap<String, Vec<String>>;
+
+/// Marks slave rows printed under their bond master
+const BOND_SLAVE_PREFIX: &str = "- ";
+/// Label of the row summing the slaves of a bond
+const BOND_SUM_LABEL: &str = "= slaves";
+
+/// Bonds among the shown interfaces with their shown slaves, from
+/// `/sys/class/net/<bond>/bonding/slaves`
+fn read_bond_slaves(speeds: &DeviceSpeeds) -> BondSlaves {
+    let mut ret = BondSlaves::new();
+    for ifname in speeds.keys() {
+        let path = std::path::Path::new(SYS_CLASS_NET_PATH)
+            .join(ifname)
+            .join("bonding")
+            .join("slaves");
+        let content = match std::fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(_) => continue,
         };
-        print!("{:>ifname_width$}", ifname);
-        for col in [stat.rx, stat.tx] {
-            if hide_zero_values && col == 0 {
-                print!(" {}", make_repeated_string(' ', number_width));
+        let slaves: Vec<String> = content
+            .split_ascii_whitespace()
+            .filter(|x| speeds.contains_key(*x))
+            .map(|x| x.to_string())
+            .collect();
+        if !slaves.is_empty() {
+            ret.insert(ifname.clone(), slaves);
+        }
+    }
+    return ret;
+}
+
+/// Humanized rate split into the number and its unit like `("1.20", "KiB/s")`.
+/// Bytes are used unless only bits are requested
+fn format_rate_parts(value: f64, options: &TableOptions) -> (String, String) {
+    if !value.is_finite() {
+        return (NO_RATE_PLACEHOLDER.to_string(), "-".to_string());
+    }
+    let (prefixes, factor, value, unit) = match options.units {
+        Units::Bits => {
+            let (prefixes, factor) = options.bit_prefixes.get_prefixes(options.raw);
+            (prefixes, factor, value * 8_f64, "bit/s")
+        }
+        Units::Bytes | Units::Both => {
+            let (prefixes, factor) = options.byte_prefixes.get_prefixes(options.raw);
+            (prefixes, factor, value, "B/s")
+        }
+    };
+    let (pretty_value, prefix) = get_human_value(value, prefixes, factor);
+    let precision = options.precision;
+    return (
+        format!("{:.precision$}", pretty_value),
+        format!("{}{}", prefix, unit),
+    );
+}
+
+/// The --flat rendering of the human table, one unaligned line per interface
+fn pretty_print_flat(
+    out: &mut dyn Write,
+    speeds: &DeviceSpeeds,
+    db: &StatisticsDb,
+    options: &TableOptions,
+) -> std::io::Result<()> {
+    let sorted_ifs = get_sorted_ifs(
+        speeds,
+        db,
+        options.sort_key,
+        options.sort_direction,
+        &options.aliases,
+    );
+    for ifname in sorted_ifs.iter().take(options.top.unwrap_or(usize::MAX)) {
+        let speed = &speeds[ifname];
+        let (rx, rx_units) = format_rate_parts(speed.rx, options);
+        let (tx, tx_units) = format_rate_parts(speed.tx, options);
+        writeln!(
+            out,
+            "{} {} {} {} {}",
+            get_display_name(ifname, &options.aliases),
+            rx,
+            rx_units,
+            tx,
+            tx_units
+        )?;
+    }
+    return Ok(());
+}
+
+/// Prints a table of already computed rates. `db` defines the order of rows.
+/// `deltas` are the byte counts behind the rates if they are known,
+/// `previous` are the rates of the previous sample for --trend and `held` are
+/// the decaying peaks of --peak-hold
+fn pretty_print_speed_table(
+    out: &mut dyn Write,
+    speeds: &DeviceSpeeds,
+    deltas: Option<&DeviceRates>,
+    previous: Option<&DeviceSpeeds>,
+    held: Option<&DeviceSpeeds>,
+    db: &StatisticsDb,
+    options: &TableOptions,
+) -> std::io::Result<()> {
+    if speeds.is_empty() {
+        writeln!(out, "No interfaces found")?;
+        return Ok(());
+    }
+    if options.totals_only {
+        let total = speeds
+            .values()
+            .fold(DeviceSpeed::default(), |acc, x| acc + *x);
+        writeln!(
+            out,
+            "Receive {} Transmit {}",
+            format_bytes_and_bits(total.rx, options),
+            format_bytes_and_bits(total.tx, options)
+        )?;
+        return Ok(());
+    }
+    if options.flat {
+        return pretty_print_flat(out, speeds, db, options);
+    }
+    let cumulative = |ifname: &String| -> DeviceStatistics {
+        return db.devices.get(ifname).copied().unwrap_or_default();
+    };
+    // Rates, cumulative counters and deltas summed over several interfaces
+    let sum_rows = |ifnames: &[&String]| -> SummedRow {
+        return (
+            ifnames
+                .iter()
+                .fold(DeviceSpeed::default(), |acc, x| acc + speeds[*x]),
+            ifnames
+                .iter()
+                .fold(DeviceStatistics::default(), |acc, x| acc + cumulative(x)),
+            deltas.map(|deltas| {
+                return ifnames.iter().fold(
+                    DeviceStatistics::default(),
+                    |acc, x| {
+                        return acc + deltas.get(*x).copied().unwrap_or_default();
+                    },
+                );
+            }),
+        );
+    };
+    let total = if options.show_total {
+        Some(sum_rows(&speeds.keys().collect::<Vec<_>>()))
+    } else {
+        None
+    };
+    let bonds = if options.bond_detail {
+        read_bond_slaves(speeds)
+    } else {
+        BondSlaves::new()
+    };
+    let bond_sums: std::collections::BTreeMap<&String, SummedRow> = bonds
+        .iter()
+        .map(|(master, slaves)| {
+            return (master, sum_rows(&slaves.iter().collect::<Vec<_>>()));
+        })
+        .collect();
+    let summed_rows = || total.iter().chain(bond_sums.values());
+    let number_width = options.column_width.unwrap_or_else(|| {
+        return speeds
+            .values()
+            .chain(summed_rows().map(|(speed, _, _)| speed))
+            .flat_map(|x| {
+                let combined = options.show_combined.then_some(x.rx + x.tx);
+                return [x.rx, x.tx].into_iter().chain(combined);
+            })
+            .map(|x| format_bytes_and_bits(x, options).chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("Transmit".len());
+    });
+    let layout = TableLayout {
+        ifname_width: options.name_width.unwrap_or_else(|| {
+            return speeds
+                .keys()
+                .map(|x| {
+                    let is_slave = bonds.values().any(|slaves| slaves.contains(x));
+                    let indent = if is_slave {
+                        BOND_SLAVE_PREFIX.chars().count()
+                    } else {
+                        0
+                    };
+                    return get_display_name(x, &options.aliases).chars().count()
+                        + indent;
+                })
+                .chain(bond_sums.keys().map(|_| BOND_SUM_LABEL.chars().count()))
+                .max()
+                .unwrap_or(0)
+                .max(10);
+        }),
+        number_width,
+        counter_width: 16,
+        counter_columns: get_counter_columns(options),
+        total_bytes_width: speeds
+            .keys()
+            .map(cumulative)
+            .chain(summed_rows().map(|(_, cumulative, _)| *cumulative))
+            .flat_map(|x| [x.rx, x.tx])
+            .map(|x| format_bytes(x as f64, options).chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("TX total".len()),
+        delta_width: deltas
+            .into_iter()
+            .flat_map(|x| x.values())
+            .chain(summed_rows().filter_map(|(_, _, delta)| delta.as_ref()))
+            .flat_map(|x| [x.rx, x.tx])
+            .map(|x| format_bytes(x as f64, options).chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("TX delta".len()),
+        hold_width: held
+            .into_iter()
+            .flat_map(|x| x.values())
+            .flat_map(|x| [x.rx, x.tx])
+            .map(|x| format_bytes_and_bits(x, options).chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("TX peak".len()),
+        pktsize_width: 12,
+        util_width: 10,
+    };
+    if !options.no_header {
+        pretty_print_header(out, &layout, options)?;
+    }
+    // Slaves are printed under their master instead of on their own
+    let sorted_ifs: Vec<String> = get_sorted_ifs(
+        speeds,
+        db,
+        options.sort_key,
+        options.sort_direction,
+        &options.aliases,
+    )
+    .into_iter()
+    .filter(|x| !bonds.values().any(|slaves| slaves.contains(x)))
+    .collect();
+    let shown_count = match options.top {
+        Some(top) => {
+            if top > sorted_ifs.len() {
+                log::warn!(
+                    "--top {top} is larger than the number of interfaces ({})",
+                    sorted_ifs.len()
+                );
+            }
+            top.min(sorted_ifs.len())
+        }
+        None => sorted_ifs.len(),
+    };
+    let print_interface =
+        |out: &mut dyn Write, label: &str, ifname: &String| -> std::io::Result<()> {
+            let link_speed = if options.show_utilization {
+                read_link_speed(ifname)
             } else {
-                pretty_print_bytes_and_bites(col as f64 / seconds, number_width);
+                None
+            };
+            return pretty_print_row(
+                out,
+                label,
+                &speeds[ifname],
+                &cumulative(ifname),
+                deltas.and_then(|x| x.get(ifname)),
+                previous.and_then(|x| x.get(ifname)),
+                held.and_then(|x| x.get(ifname)),
+                link_speed,
+                &layout,
+                options,
+            );
+        };
+    let print_sum =
+        |out: &mut dyn Write, label: &str, row: &SummedRow| -> std::io::Result<()> {
+            let (speed, cumulative, delta) = row;
+            return pretty_print_row(
+                out,
+                label,
+                speed,
+                cumulative,
+                delta.as_ref(),
+                None,
+                None,
+                None,
+                &layout,
+                options,
+            );
+        };
+    for ifname in sorted_ifs.iter().take(shown_count) {
+        print_interface(out, get_display_name(ifname, &options.aliases), ifname)?;
+        if let Some(slaves) = bonds.get(ifname) {
+            for slave in slaves.iter() {
+                let label = format!(
+                    "{}{}",
+                    BOND_SLAVE_PREFIX,
+                    get_display_name(slave, &options.aliases)
+                );
+                print_interface(out, &label, slave)?;
             }
+            print_sum(out, BOND_SUM_LABEL, &bond_sums[ifname])?;
         }
-        println!();
     }
+    if let Some(total) = &total {
+        print_sum(out, "Total", total)?;
+    }
+    return Ok(());
 }
 
-/// A program analogous to ifstat from iproute2 package
-/// (https://archlinux.org/packages/core/x86_64/iproute2/). Shows network device speed from
-/// /proc/net/dev. See man 5 proc
-#[derive(Debug, clap::Parser)]
-#[command(version, about, long_about = None)]
-struct Cli {
-    /// Name of a history file
-    #[arg(short = 'f', long)]
-    history_file: String,
+/// Per-second rates of all counters of DeviceStatistics
+#[derive(Copy, Clone, Default, serde::Serialize)]
+struct DeviceSpeed {
+    rx: f64,
+    tx: f64,
+    rx_packets: f64,
+    tx_packets: f64,
+    rx_errors: f64,
+    tx_errors: f64,
+    rx_drops: f64,
+    tx_drops: f64,
+}
+impl DeviceSpeed {
+    fn new(stat: &DeviceStatistics, seconds: f64) -> Self {
+        return Self {
+            rx: stat.rx as f64 / seconds,
+            tx: stat.tx as f64 / seconds,
+            rx_packets: stat.rx_packets as f64 / seconds,
+            tx_packets: stat.tx_packets as f64 / seconds,
+            rx_errors: stat.rx_errors as f64 / seconds,
+            tx_errors: stat.tx_errors as f64 / seconds,
+            rx_drops: stat.rx_drops as f64 / seconds,
+            tx_drops: stat.tx_drops as f64 / seconds,
+        };
+    }
 
-    /// Hide interfaces with zero statistics
-    #[arg(long)]
-    hide_zero_ifs: bool,
+    /// Element-wise maximum
+    fn max(&self, other: &Self) -> Self {
+        return Self {
+            rx: self.rx.max(other.rx),
+            tx: self.tx.max(other.tx),
+            rx_packets: self.rx_packets.max(other.rx_packets),
+            tx_packets: self.tx_packets.max(other.tx_packets),
+            rx_errors: self.rx_errors.max(other.rx_errors),
+            tx_errors: self.tx_errors.max(other.tx_errors),
+            rx_drops: self.rx_drops.max(other.rx_drops),
+            tx_drops: self.tx_drops.max(other.tx_drops),
+        };
+    }
+}
+impl std::ops::AddAssign for DeviceSpeed {
+    fn add_assign(&mut self, rhs: Self) {
+        self.rx += rhs.rx;
+        self.tx += rhs.tx;
+        self.rx_packets += rhs.rx_packets;
+        self.tx_packets += rhs.tx_packets;
+        self.rx_errors += rhs.rx_errors;
+        self.tx_errors += rhs.tx_errors;
+        self.rx_drops += rhs.rx_drops;
+        self.tx_drops += rhs.tx_drops;
+    }
+}
+impl std::ops::Mul<f64> for DeviceSpeed {
+    type Output = Self;
+    fn mul(self, rhs: f64) -> Self::Output {
+        return Self {
+            rx: self.rx * rhs,
+            tx: self.tx * rhs,
+            rx_packets: self.rx_packets * rhs,
+            tx_packets: self.tx_packets * rhs,
+            rx_errors: self.rx_errors * rhs,
+            tx_errors: self.tx_errors * rhs,
+            rx_drops: self.rx_drops * rhs,
+            tx_drops: self.tx_drops * rhs,
+        };
+    }
+}
+impl std::ops::Add<DeviceSpeed> for DeviceSpeed {
+    type Output = Self;
+    fn add(self, rhs: DeviceSpeed) -> Self::Output {
+        let mut tmp = self;
+        tmp += rhs;
+        return tmp;
+    }
+}
 
-    /// Hide zeros from fields
-    #[arg(long)]
-    hide_zero_values: bool,
+type DeviceSpeeds = std::collections::BTreeMap<String, DeviceSpeed>;
 
-    /// Sort devices by total statistics instead of alphabetically
-    #[arg(long)]
-    sort_by_stat: bool,
+fn get_device_speeds(diff: &DeviceRates, seconds: f64) -> DeviceSpeeds {
+    return diff
+        .iter()
+        .map(|(k, v)| (k.clone(), DeviceSpeed::new(v, seconds)))
+        .collect();
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
+/// Keeps the highest rates seen per interface during a watch session. An
+/// interface which disappears loses its peak. Samples without an interval,
+/// e.g. after the clock jumped backward, have no rates and are skipped
+fn update_peak_speeds(
+    peaks: &mut DeviceSpeeds,
+    speeds: &DeviceSpeeds,
+    seconds: f64,
+) {
+    if seconds <= 0_f64 {
+        return;
+    }
+    peaks.retain(|k, _| speeds.contains_key(k));
+    for (ifname, speed) in speeds.iter() {
+        let peak = peaks.entry(ifname.clone()).or_insert(*speed);
+        *peak = peak.max(speed);
+    }
+}
 
-    use clap::Parser;
-    let args = Cli::parse();
+/// Peak rates for --peak-hold which fall by `decay` every sample unless the
+/// current rate is higher, like the peak indicator of a VU meter
+struct PeakHold {
+    decay: f64,
+    peaks: DeviceSpeeds,
+}
+impl PeakHold {
+    fn new(decay: f64) -> Self {
+        return Self {
+            decay,
+            peaks: DeviceSpeeds::new(),
+        };
+    }
 
-    if is_file_exist(&args.history_file) {
-        log::debug!("File `{}` exists", args.history_file);
-        let a = parse_stat_db(&args.history_file)?;
-        let b = parse_proc_net_dev(args.hide_zero_ifs).with_context(|| {
-            format!("Failed to parse {} file", PROC_NET_DEV_PATH)
-        })?;
-        let diff = subtract_device_rates(&b.devices, &a.devices);
-        dump_stat_db(&args.history_file, &b)
-            .context("Failed to update statistics db")?;
-        let interval = (b.timestamp - a.timestamp)
-            .to_std()
-            .context("Duration is negative!")?
-            .as_secs_f64();
-        log::debug!("Interval = {} s", interval);
-        pretty_print_devices_speed(
-            &diff,
-            &b,
-            interval,
-            args.hide_zero_values,
-            args.sort_by_stat,
-        );
-    } else {
-        log::debug!("File `{}` does not exist", args.history_file);
-        let a = parse_proc_net_dev(args.hide_zero_ifs).with_context(|| {
-            format!("Failed to parse {} file", PROC_NET_DEV_PATH)
-        })?;
-        dump_stat_db(&args.history_file, &a)
-            .context("Failed to update statistics db")?;
-        pretty_print_devices_speed(
-            &a.devices,
-            &a,
-            0_f64,
-            args.hide_zero_values,
-            args.sort_by_stat,
-        );
+    /// An interface which disappears loses its peak
+    fn update(&mut self, speeds: &DeviceSpeeds) {
+        self.peaks.retain(|k, _| speeds.contains_key(k));
+        for (ifname, speed) in speeds.iter() {
+            let peak = self.peaks.entry(ifname.clone()).or_insert(*speed);
+            *peak = (*peak * self.decay).max(speed);
+        }
     }
+}
 
-    return Ok(());
+/// Traffic of one interface accumulated over a watch session for --summary
+#[derive(Default)]
+struct SessionTotals {
+    bytes: DeviceStatistics,
+    seconds: f64,
+    peak: DeviceSpeed,
+}
+
+/// Unlike the peaks of --show-peak, interfaces which disappear keep their
+/// totals since the traffic did happen
+fn update_session_totals(
+    totals: &mut std::collections::BTreeMap<String, SessionTotals>,
+    diff: &DeviceRates,
+    seconds: f64,
+) {
+    for (ifname, stat) in diff.iter() {
+        let entry = totals.entry(ifname.clone()).or_default();
+        entry.bytes += *stat;
+        entry.seconds += seconds;
+        entry.peak = entry.peak.max(&DeviceSpeed::new(stat, seconds));
+    }
+}
+
+fn pretty_print_session_summary(
+    out: &mut dyn Write,
+    totals: &std::collections::BTreeMap<String, SessionTotals>,
+    last: &StatisticsDb,
+    options: &TableOptions,
+) -> std::io::Result<()> {
+    // Byte totals in place of rates sort by the traffic of the whole session
+    let sort_keys: DeviceSpeeds = totals
+        .iter()
+        .map(|(k, v)| (k.clone(), DeviceSpeed::new(&v.bytes, 1_f64)))
+        .collect();
+    let mut rows = vec![[
+        "Interface",
+        "RX total",
+        "TX total",
+        "RX avg",
+        "TX avg",
+        "RX peak",
+        "TX peak",
+    ]
+    .map(|x| x.to_string())];
+    for ifname in get_sorted_ifs(
+        &sort_keys,
+        last,
+        options.sort_key,
+        options.sort_direction,
+        &options.aliases,
+    ) {
+        let x = &totals[&ifname];
+        let average = DeviceSpeed::new(&x.bytes, x.seconds);
+        rows.push([
+            get_display_name(&ifname, &options.aliases).to_string(),
+            format_bytes(x.bytes.rx as f64, options),
+            format_bytes(x.bytes.tx as f64, options),
+            format_bytes_and_bits(average.rx, options),
+            format_bytes_and_bits(average.tx, options),
+            format_bytes_and_bits(x.peak.rx, options),
+            format_bytes_and_bits(x.peak.tx, options),
+        ]);
+    }
+    let mut widths = [0; 7];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in rows.iter() {
+        let ifname_width = widths[0];
+        write!(out, "{:>ifname_width$}", row[0])?;
+        for (width, cell) in widths.iter().zip(row.iter()).skip(1) {
+            write!(out, " {:>width$}", cell)?;
+        }
+        writeln!(out)?;
+    }
+    return Ok(());
+}
+
+/// Ring buffer of the last samples per interface for --average. Interfaces
+/// which appear mid-window are averaged over the samples available so far
+struct RateAverager {
+    window: usize,
+    history: std::collections::BTreeMap<
+        String,
+        std::collections::VecDeque<(DeviceStatistics, f64)>,
+    >,
+}
+impl RateAverager {
+    fn new(window: usize) -> Self {
+        let history = std::collections::BTreeMap::new();
+        return Self { window, history };
+    }
+
+    fn push(&mut self, diff: &DeviceRates, seconds: f64) {
+        self.history.retain(|k, _| diff.contains_key(k));
+        for (ifname, stat) in diff.iter() {
+            let samples = self.history.entry(ifname.clone()).or_default();
+            if samples.len() == self.window {
+                samples.pop_front();
+            }
+            samples.push_back((*stat, seconds));
+        }
+    }
+
+    /// Mean rates weighted by the length of each sample
+    fn get_speeds(&self) -> DeviceSpeeds {
+        let mut ret = DeviceSpeeds::new();
+        for (ifname, samples) in self.history.iter() {
+            let (sum, seconds) = samples.iter().fold(
+                (DeviceStatistics::default(), 0_f64),
+                |(sum, seconds), (stat, s)| (sum + *stat, seconds + s),
+            );
+            ret.insert(ifname.clone(), DeviceSpeed::new(&sum, seconds));
+        }
+        return ret;
+    }
+}
+
+/// Computed rates in bytes per second as they are printed with `--format json`
+#[derive(serde::Serialize)]
+struct SpeedReport {
+    timestamp: chrono::DateTime<chrono::Utc>,
+    interval: f64,
+    devices: DeviceSpeeds,
+}
+impl SpeedReport {
+    fn new(speeds: &DeviceSpeeds, db: &StatisticsDb, seconds: f64) -> Self {
+        return Self {
+            timestamp: db.timestamp,
+            interval: seconds,
+            devices: speeds.clone(),
+        };
+    }
+}
+
+fn print_json_report(
+    out: &mut dyn Write,
+    speeds: &DeviceSpeeds,
+    db: &StatisticsDb,
+    seconds: f64,
+    pretty: bool,
+) -> anyhow::Result<()> {
+    let report = SpeedReport::new(speeds, db, seconds);
+    // Serialized first, so a closed pipe surfaces as a plain io error
+    let data = if pretty {
+        serde_json::to_vec_pretty(&report)
+    } else {
+        serde_json::to_vec(&report)
+    }
+    .context("Serialization failed")?;
+    out.write_all(&data)?;
+    writeln!(out)?;
+    out.flush().context("Flush failed")?;
+    return Ok(());
+}
+
+#[cfg(unix)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum SyslogFacility {
+    User,
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+#[cfg(unix)]
+impl SyslogFacility {
+    fn get_facility(self) -> syslog::Facility {
+        return match self {
+            SyslogFacility::User => syslog::Facility::LOG_USER,
+            SyslogFacility::Daemon => syslog::Facility::LOG_DAEMON,
+            SyslogFacility::Local0 => syslog::Facility::LOG_LOCAL0,
+            SyslogFacility::Local1 => syslog::Facility::LOG_LOCAL1,
+            SyslogFacility::Local2 => syslog::Facility::LOG_LOCAL2,
+            SyslogFacility::Local3 => syslog::Facility::LOG_LOCAL3,
+            SyslogFacility::Local4 => syslog::Facility::LOG_LOCAL4,
+            SyslogFacility::Local5 => syslog::Facility::LOG_LOCAL5,
+            SyslogFacility::Local6 => syslog::Facility::LOG_LOCAL6,
+            SyslogFacility::Local7 => syslog::Facility::LOG_LOCAL7,
+        };
+    }
+}
+
+/// Sends RFC 3164 messages to the local syslog daemon
+#[cfg(unix)]
+struct SyslogWriter {
+    logger: syslog::Logger<syslog::LoggerBackend, syslog::Formatter3164>,
+}
+#[cfg(unix)]
+impl SyslogWriter {
+    fn connect(facility: SyslogFacility, tag: &str) -> anyhow::Result<Self> {
+        let formatter = syslog::Formatter3164 {
+            facility: facility.get_facility(),
+            hostname: None,
+            process: tag.to_string(),
+            pid: std::process::id(),
+        };
+        let logger =
+            syslog::unix(formatter).context("Failed to connect to syslog")?;
+        return Ok(Self { logger });
+    }
+
+    fn send(&mut self, message: &str) -> anyhow::Result<()> {
+        self.logger
+            .info(message)
+            .context("Failed to send to syslog")?;
+        return Ok(());
+    }
+}
+
+/// One line with rates of all interfaces in bytes per second
+#[cfg(unix)]
+fn format_syslog_report(
+    speeds: &DeviceSpeeds,
+    db: &StatisticsDb,
+    sort_key: SortKey,
+    sort_direction: SortDirection,
+) -> String {
+    return get_sorted_ifs(
+        speeds,
+        db,
+        sort_key,
+        sort_direction,
+        &InterfaceAliases::new(),
+    )
+    .into_iter()
+    .map(|ifname| {
+        let speed = speeds[&ifname];
+        return format!("{} rx={:.2} tx={:.2}", ifname, speed.rx, speed.tx);
+    })
+    .collect::<Vec<_>>()
+    .join(" ");
+}
+
+/// Direction markers of the oneline format
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum OnelineLabels {
+    /// ↓ for receive and ↑ for transmit
+    Arrows,
+    /// rx and tx words
+    Text,
+}
+impl OnelineLabels {
+    fn get_labels(self) -> (&'static str, &'static str) {
+        return match self {
+            OnelineLabels::Arrows => ("↓", "↑"),
+            OnelineLabels::Text => ("rx ", "tx "),
+        };
+    }
+}
+
+/// All interfaces on a single line like `eth0 ↓1.20Mi ↑340.00Ki | wg0 ...`
+/// with byte rates shortened to their prefix, for status bars
+fn format_oneline_report(
+    speeds: &DeviceSpeeds,
+    db: &StatisticsDb,
+    options: &TableOptions,
+    labels: OnelineLabels,
+) -> String {
+    let (rx_label, tx_label) = labels.get_labels();
+    let format_rate = |value: f64| -> String {
+        if !value.is_finite() {
+            return NO_RATE_PLACEHOLDER.to_string();
+        }
+        let (prefixes, factor) = options.byte_prefixes.get_prefixes(options.raw);
+        let (pretty_value, prefix) = get_human_value(value, prefixes, factor);
+        let precision = options.precision;
+        return format!("{:.precision$}{}", pretty_value, prefix);
+    };
+    return get_sorted_ifs(
+        speeds,
+        db,
+        options.sort_key,
+        options.sort_direction,
+        &options.aliases,
+    )
+    .into_iter()
+    .map(|ifname| {
+        let speed = speeds[&ifname];
+        return format!(
+            "{} {}{} {}{}",
+            get_display_name(&ifname, &options.aliases),
+            rx_label,
+            format_rate(speed.rx),
+            tx_label,
+            format_rate(speed.tx)
+        );
+    })
+    .take(options.top.unwrap_or(usize::MAX))
+    .collect::<Vec<_>>()
+    .join(" | ");
+}
+
+fn print_yaml_report(
+    out: &mut dyn Write,
+    speeds: &DeviceSpeeds,
+    db: &StatisticsDb,
+    seconds: f64,
+) -> anyhow::Result<()> {
+    let report = SpeedReport::new(speeds, db, seconds);
+    let data = serde_yaml::to_string(&report).context("Serialization failed")?;
+    // Separates the documents of watch mode
+    writeln!(out, "---")?;
+    out.write_all(data.as_bytes())?;
+    return Ok(());
+}
+
+fn csv_escape(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", field.replace('"', "\"\"")).into();
+    }
+    return field.into();
+}
+
+/// Field separator of the delimited formats. Tsv fields are never quoted
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Delimiter {
+    Comma,
+    Tab,
+}
+impl Delimiter {
+    fn as_str(self) -> &'static str {
+        return match self {
+            Delimiter::Comma => ",",
+            Delimiter::Tab => "\t",
+        };
+    }
+
+    fn escape(self, field: &str) -> std::borrow::Cow<'_, str> {
+        return match self {
+            Delimiter::Comma => csv_escape(field),
+            Delimiter::Tab => field.into(),
+        };
+    }
+}
+
+/// Columns of csv and tsv output when --fields is not given
+const DEFAULT_DELIMITED_FIELDS: &[Field] = &[
+    Field::Interface,
+    Field::RxBytesPerSec,
+    Field::TxBytesPerSec,
+    Field::Timestamp,
+];
+
+/// Everything known about one interface that --fields can select from
+struct FieldRow<'a> {
+    ifname: String,
+    speed: DeviceSpeed,
+    /// Cumulative counters
+    total: DeviceStatistics,
+    timestamp: &'a str,
+}
+
+impl Field {
+    fn get_name(self) -> String {
+        use clap::ValueEnum;
+        return self
+            .to_possible_value()
+            .expect("no skipped variants")
+            .get_name()
+            .to_string();
+    }
+
+    /// Plain value for delimited output, or a humanized one for the table
+    /// when `options` are given. Unknown plain rates are empty cells
+    fn format(self, row: &FieldRow, options: Option<&TableOptions>) -> String {
+        let plain = |value: f64| -> String {
+            if !value.is_finite() {
+                return String::new();
+            }
+            return value.to_string();
+        };
+        let rate = |value: f64| -> String {
+            return match options {
+                Some(options) => format_bytes_and_bits(value, options),
+                None => plain(value),
+            };
+        };
+        let per_second = |value: f64, unit: &str| -> String {
+            return match options {
+                Some(options) => format_per_second(value, unit, options),
+                None => plain(value),
+            };
+        };
+        let amount = |value: u64| -> String {
+            return match options {
+                Some(options) => format_bytes(value as f64, options),
+                None => value.to_string(),
+            };
+        };
+        return match self {
+            Field::Interface => match options {
+                Some(options) => get_display_name(&row.ifname, &options.aliases),
+                None => &row.ifname,
+            }
+            .to_string(),
+            Field::RxBytesPerSec => rate(row.speed.rx),
+            Field::TxBytesPerSec => rate(row.speed.tx),
+            Field::RxPacketsPerSec => per_second(row.speed.rx_packets, "pkt"),
+            Field::TxPacketsPerSec => per_second(row.speed.tx_packets, "pkt"),
+            Field::RxErrorsPerSec => per_second(row.speed.rx_errors, "err"),
+            Field::TxErrorsPerSec => per_second(row.speed.tx_errors, "err"),
+            Field::RxDropsPerSec => per_second(row.speed.rx_drops, "drop"),
+            Field::TxDropsPerSec => per_second(row.speed.tx_drops, "drop"),
+            Field::RxTotal => amount(row.total.rx),
+            Field::TxTotal => amount(row.total.tx),
+            Field::Timestamp => row.timestamp.to_string(),
+        };
+    }
+}
+
+/// Rows of the selected interfaces in the order they are printed
+fn get_field_rows<'a>(
+    speeds: &DeviceSpeeds,
+    db: &'a StatisticsDb,
+    timestamp: &'a str,
+    sort_key: SortKey,
+    sort_direction: SortDirection,
+    aliases: &InterfaceAliases,
+) -> Vec<FieldRow<'a>> {
+    return get_sorted_ifs(speeds, db, sort_key, sort_direction, aliases)
+        .into_iter()
+        .filter_map(|ifname| {
+            let speed = *speeds.get(&ifname)?;
+            let total = db.devices.get(&ifname).copied().unwrap_or_default();
+            return Some(FieldRow {
+                ifname,
+                speed,
+                total,
+                timestamp,
+            });
+        })
+        .collect();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn print_delimited_report(
+    out: &mut dyn Write,
+    speeds: &DeviceSpeeds,
+    db: &StatisticsDb,
+    sort_key: SortKey,
+    sort_direction: SortDirection,
+    no_header: bool,
+    delimiter: Delimiter,
+    fields: &[Field],
+) -> std::io::Result<()> {
+    let fields = if fields.is_empty() {
+        DEFAULT_DELIMITED_FIELDS
+    } else {
+        fields
+    };
+    let sep = delimiter.as_str();
+    if !no_header {
+        let names: Vec<String> = fields.iter().map(|x| x.get_name()).collect();
+        writeln!(out, "{}", names.join(sep))?;
+    }
+    let timestamp = db.timestamp.to_rfc3339();
+    for row in get_field_rows(
+        speeds,
+        db,
+        &timestamp,
+        sort_key,
+        sort_direction,
+        &InterfaceAliases::new(),
+    ) {
+        let values: Vec<String> = fields
+            .iter()
+            .map(|x| delimiter.escape(&x.format(&row, None)).into_owned())
+            .collect();
+        writeln!(out, "{}", values.join(sep))?;
+    }
+    return Ok(());
+}
+
+/// Aligned table of only the columns picked with --fields. The interface
+/// name is left-aligned and every other value is right-aligned
+fn pretty_print_fields_table(
+    out: &mut dyn Write,
+    speeds: &DeviceSpeeds,
+    db: &StatisticsDb,
+    fields: &[Field],
+    options: &TableOptions,
+) -> std::io::Result<()> {
+    if speeds.is_empty() {
+        writeln!(out, "No interfaces found")?;
+        return Ok(());
+    }
+    let timestamp = format_timestamp(&db.timestamp, None);
+    let mut rows: Vec<Vec<String>> = get_field_rows(
+        speeds,
+        db,
+        &timestamp,
+        options.sort_key,
+        options.sort_direction,
+        &options.aliases,
+    )
+    .into_iter()
+    .take(options.top.unwrap_or(usize::MAX))
+    .map(|row| {
+        return fields
+            .iter()
+            .map(|x| x.format(&row, Some(options)))
+            .collect();
+    })
+    .collect();
+    if !options.no_header {
+        rows.insert(0, fields.iter().map(|x| x.get_name()).collect());
+    }
+    let widths: Vec<usize> = (0..fields.len())
+        .map(|i| {
+            return rows
+                .iter()
+                .map(|x| x[i].chars().count())
+                .max()
+                .unwrap_or_default();
+        })
+        .collect();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(fields.iter().zip(widths.iter()))
+            .map(|(value, (field, width))| {
+                return if *field == Field::Interface {
+                    format!("{:<width$}", value)
+                } else {
+                    format!("{:>width$}", value)
+                };
+            })
+            .collect();
+        writeln!(out, "{}", cells.join("  ").trim_end())?;
+    }
+    return Ok(());
+}
+
+fn prometheus_escape(label_value: &str) -> String {
+    return label_value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+}
+
+fn prometheus_value(value: f64) -> String {
+    if value.is_nan() {
+        return "NaN".to_string();
+    } else if value.is_infinite() {
+        return if value > 0_f64 { "+Inf" } else { "-Inf" }.to_string();
+    }
+    return value.to_string();
+}
+
+/// Renders rates in the Prometheus text exposition format. Without a baseline
+/// the rates are unknown, so only the HELP and TYPE lines are written
+fn format_prometheus_report(
+    speeds: &DeviceSpeeds,
+    db: &StatisticsDb,
+    seconds: f64,
+    sort_key: SortKey,
+    sort_direction: SortDirection,
+) -> String {
+    let sorted_ifs: Vec<(String, DeviceSpeed)> = get_sorted_ifs(
+        speeds,
+        db,
+        sort_key,
+        sort_direction,
+        &InterfaceAliases::new(),
+    )
+    .into_iter()
+    .filter(|_| seconds > 0_f64)
+    .map(|ifname| {
+        let speed = speeds[&ifname];
+        return (ifname, speed);
+    })
+    .collect();
+    let mut ret = String::new();
+    for (direction, help) in [("rx", "Received"), ("tx", "Transmitted")] {
+        let name = format!("ifstat_{direction}_bytes_per_second");
+        ret += &format!("# HELP {name} {help} bytes per second\n");
+        ret += &format!("# TYPE {name} gauge\n");
+        for (ifname, speed) in sorted_ifs.iter() {
+            let value = if direction == "rx" {
+                speed.rx
+            } else {
+                speed.tx
+            };
+            ret += &format!(
+                "{name}{{interface=\"{}\"}} {}\n",
+                prometheus_escape(ifname),
+                prometheus_value(value)
+            );
+        }
+    }
+    return ret;
+}
+
+fn write_http_response(
+    stream: &mut std::net::TcpStream,
+    status: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    return stream.flush();
+}
+
+/// How long --serve waits for a client to send its request or accept the
+/// response
+const SERVE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Blocking single-threaded HTTP server answering `GET /metrics` with rates
+/// against the previous scrape. The first scrape has no baseline and returns
+/// metric headers without samples
+fn serve_metrics(
+    addr: &str,
+    read_current_stats: &dyn Fn() -> anyhow::Result<StatisticsDb>,
+    args: &Cli,
+) -> anyhow::Result<()> {
+    let listener = std::net::TcpListener::bind(addr)
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    log::info!("Serving metrics on http://{}/metrics", addr);
+    let mut prev: Option<StatisticsDb> = None;
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(x) => x,
+            Err(e) => {
+                log::warn!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+        // Connections are served one at a time, so an idle client must not
+        // block the scrapes after it
+        let timeouts = stream
+            .set_read_timeout(Some(SERVE_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(SERVE_TIMEOUT)));
+        if let Err(e) = timeouts {
+            log::warn!("Failed to set socket timeouts: {e}");
+            continue;
+        }
+        let mut request_line = String::new();
+        let mut reader = std::io::BufReader::new(&stream);
+        if let Err(e) = reader.read_line(&mut request_line) {
+            log::warn!("Failed to read request: {e}");
+            continue;
+        }
+        // Drain headers, the request body is never needed
+        let mut header = String::new();
+        while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+            header.clear();
+        }
+        let mut parts = request_line.split_ascii_whitespace();
+        let method = parts.next().unwrap_or("");
+        let path = parts.next().unwrap_or("");
+        log::debug!("{method} {path}");
+        let result = if method != "GET" {
+            write_http_response(&mut stream, "405 Method Not Allowed", "")
+        } else if path != "/metrics" {
+            write_http_response(&mut stream, "404 Not Found", "")
+        } else {
+            let report = read_current_stats().and_then(|current| {
+                let (diff, interval) = match &prev {
+                    Some(_) => compute_diff(prev.as_ref(), &current, args)?,
+                    None => (DeviceRates::new(), 0_f64),
+                };
+                let body = format_prometheus_report(
+                    &get_device_speeds(&diff, interval),
+                    &current,
+                    interval,
+                    args.get_sort_key(),
+                    args.get_sort_direction(),
+                );
+                prev = Some(current);
+                return Ok(body);
+            });
+            match report {
+                Ok(body) => write_http_response(&mut stream, "200 OK", &body),
+                Err(e) => {
+                    log::error!("{e:#}");
+                    write_http_response(
+                        &mut stream,
+                        "500 Internal Server Error",
+                        &format!("{e:#}\n"),
+                    )
+                }
+            }
+        };
+        if let Err(e) = result {
+            log::warn!("Failed to write response: {e}");
+        }
+    }
+    return Ok(());
+}
+
+static INTERRUPTED: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(false);
+
+extern "C" fn on_sigint(_signum: std::ffi::c_int) {
+    INTERRUPTED.store(true, std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Replaces the default SIGINT action so that Ctrl-C only raises a flag and the
+/// watch loop can stop between polls instead of in the middle of a write
+fn install_sigint_handler() -> anyhow::Result<()> {
+    extern "C" {
+        fn signal(signum: std::ffi::c_int, handler: usize) -> usize;
+    }
+    const SIGINT: std::ffi::c_int = 2;
+    const SIG_ERR: usize = usize::MAX;
+    let handler = on_sigint as extern "C" fn(std::ffi::c_int) as usize;
+    if unsafe { signal(SIGINT, handler) } == SIG_ERR {
+        return Err(anyhow::anyhow!("Failed to install SIGINT handler"));
+    }
+    return Ok(());
+}
+
+fn is_interrupted() -> bool {
+    return INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Sleeps in short slices so that Ctrl-C is noticed promptly. Returns false if
+/// interrupted
+fn sleep_interruptible(duration: std::time::Duration) -> bool {
+    let slice = std::time::Duration::from_millis(100);
+    // A deadline beyond what Instant can represent is never reached
+    let deadline = std::time::Instant::now().checked_add(duration);
+    loop {
+        if is_interrupted() {
+            return false;
+        }
+        let now = std::time::Instant::now();
+        match deadline {
+            Some(deadline) if now >= deadline => return true,
+            Some(deadline) => std::thread::sleep(slice.min(deadline - now)),
+            None => std::thread::sleep(slice),
+        }
+    }
+}
+
+/// Attempts to read the counters in watch mode before giving up
+const READ_ATTEMPTS: u32 = 4;
+/// Wait before the first retry of a failed read. It doubles with every retry
+const READ_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Retries transient read failures of watch mode, e.g. during namespace
+/// teardown, so that one bad read does not end a long running monitor
+fn read_with_retry(
+    read_current_stats: &dyn Fn() -> anyhow::Result<StatisticsDb>,
+) -> anyhow::Result<StatisticsDb> {
+    let mut delay = READ_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        let e = match read_current_stats() {
+            Ok(x) => return Ok(x),
+            Err(e) => e,
+        };
+        if attempt >= READ_ATTEMPTS {
+            return Err(e.context(format!("Giving up after {} attempts", attempt)));
+        }
+        log::warn!(
+            "{:#}. Retrying in {} ms ({}/{})",
+            e,
+            delay.as_millis(),
+            attempt,
+            READ_ATTEMPTS - 1
+        );
+        if !sleep_interruptible(delay) {
+            return Err(e);
+        }
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// O_NONBLOCK from fcntl.h
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const O_NONBLOCK: i32 = 0o4000;
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+const O_NONBLOCK: i32 = 0x0004;
+
+/// Opens the --output file. Opening a FIFO without a reader would block, so
+/// it fails instead
+fn open_output_file(path: &str, append: bool) -> anyhow::Result<std::fs::File> {
+    #[cfg(unix)]
+    let _probe = {
+        use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+        let is_fifo = std::fs::metadata(path).is_ok_and(|x| x.file_type().is_fifo());
+        if is_fifo {
+            // A non-blocking open fails with ENXIO when nobody reads. The probe
+            // stays open until the real file is, so the reader never sees EOF
+            Some(
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .custom_flags(O_NONBLOCK)
+                    .open(path)
+                    .with_context(|| {
+                        format!("No process is reading the FIFO {}", path)
+                    })?,
+            )
+        } else {
+            None
+        }
+    };
+    return std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .with_context(|| format!("Failed to open output file {}", path));
+}
+
+/// Whether the error was caused by writing to a pipe whose reader is gone
+fn is_broken_pipe(e: &anyhow::Error) -> bool {
+    return e.chain().any(|x| {
+        return x
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|x| x.kind() == std::io::ErrorKind::BrokenPipe);
+    });
+}
+
+fn clear_screen(out: &mut dyn Write) -> std::io::Result<()> {
+    write!(out, "\x1b[2J\x1b[H")?;
+    return Ok(());
+}
+
+fn parse_time_format(s: &str) -> Result<String, String> {
+    let has_errors = chrono::format::StrftimeItems::new(s)
+        .any(|x| matches!(x, chrono::format::Item::Error));
+    if has_errors {
+        return Err(format!("'{s}' is not a valid strftime format"));
+    }
+    return Ok(s.to_string());
+}
+
+fn parse_decay(s: &str) -> Result<f64, String> {
+    return match s.parse::<f64>() {
+        Ok(x) if x > 0_f64 && x < 1_f64 => Ok(x),
+        Ok(_) => Err("must be between 0 and 1 exclusive".to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+}
+
+fn parse_anomaly_margin(s: &str) -> Result<f64, String> {
+    return match s.parse::<f64>() {
+        Ok(x) if x >= 1_f64 => Ok(x),
+        Ok(_) => Err("must be at least 1".to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+}
+
+fn parse_alias(s: &str) -> Result<(String, String), String> {
+    return match s.split_once('=') {
+        Some((ifname, alias)) if !ifname.is_empty() && !alias.is_empty() => {
+            Ok((ifname.to_string(), alias.to_string()))
+        }
+        _ => Err("expected IFNAME=ALIAS".to_string()),
+    };
+}
+
+/// Parses the time part of an ISO 8601 duration after `PT`, like `1H30M`
+fn parse_iso_duration(s: &str) -> Result<f64, String> {
+    let mut ret = 0_f64;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let factor = match c.to_ascii_uppercase() {
+            'H' => 3600_f64,
+            'M' => 60_f64,
+            'S' => 1_f64,
+            _ => return Err(format!("unknown unit '{c}' in an ISO 8601 duration")),
+        };
+        let value = number
+            .parse::<f64>()
+            .map_err(|_| format!("missing number before '{c}'"))?;
+        ret += value * factor;
+        number.clear();
+    }
+    if s.is_empty() || !number.is_empty() {
+        return Err("an ISO 8601 duration must end with H, M or S".to_string());
+    }
+    return Ok(ret);
+}
+
+/// Parses seconds like `0.5`, a number with an `s`, `m` or `h` suffix like
+/// `5m`, or an ISO 8601 duration like `PT1M30S`
+fn parse_interval(s: &str) -> Result<f64, String> {
+    let value = if let Some(rest) = s.strip_prefix("PT").or(s.strip_prefix("pt")) {
+        parse_iso_duration(rest)
+            .map_err(|e| format!("invalid duration '{s}': {e}"))?
+    } else {
+        let (number, factor) = match s.char_indices().last() {
+            Some((i, 's')) => (&s[..i], 1_f64),
+            Some((i, 'm')) => (&s[..i], 60_f64),
+            Some((i, 'h')) => (&s[..i], 3600_f64),
+            _ => (s, 1_f64),
+        };
+        number
+            .parse::<f64>()
+            .map_err(|e| format!("'{s}' is not a number or a duration: {e}"))?
+            * factor
+    };
+    if !value.is_finite() || value <= 0_f64 {
+        return Err(format!(
+            "interval must be a positive number of seconds, got {s}"
+        ));
+    }
+    // Checked here so clap reports it instead of a panic when sleeping
+    std::time::Duration::try_from_secs_f64(value)
+        .map_err(|e| format!("interval {s} is too long: {e}"))?;
+    return Ok(value);
+}
+
+/// Inverse of get_human_value. Parses sizes like `500k`, `10M`, `1.5GiB` or
+/// a bare number. K, M, G and T are decimal in either case, adding `i` makes
+/// them binary. A trailing `B` is ignored
+fn parse_human_size(s: &str) -> anyhow::Result<f64> {
+    let trimmed = s.trim();
+    let without_unit = trimmed.strip_suffix('B').unwrap_or(trimmed);
+    let (without_i, binary) = match without_unit.strip_suffix('i') {
+        Some(x) => (x, true),
+        None => (without_unit, false),
+    };
+    let base = if binary { 1024_f64 } else { 1000_f64 };
+    let (number, exponent) = match without_i.chars().last() {
+        Some('k' | 'K') => (&without_i[..without_i.len() - 1], 1),
+        Some('m' | 'M') => (&without_i[..without_i.len() - 1], 2),
+        Some('g' | 'G') => (&without_i[..without_i.len() - 1], 3),
+        Some('t' | 'T') => (&without_i[..without_i.len() - 1], 4),
+        _ if binary => {
+            return Err(anyhow::anyhow!(
+                "'{s}' has an `i` without a K, M, G or T prefix"
+            ))
+        }
+        _ => (without_i, 0),
+    };
+    let value = number
+        .parse::<f64>()
+        .with_context(|| format!("'{s}' is not a size"))?;
+    if !value.is_finite() || value < 0_f64 {
+        return Err(anyhow::anyhow!(
+            "size must be a non-negative number, got {s}"
+        ));
+    }
+    return Ok(value * base.powi(exponent));
+}
+
+/// Nagios plugin states, used as the exit code
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum ThresholdState {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+}
+
+/// Writes a status line for every interface whose rate exceeds a --warn-* or
+/// --crit-* threshold and returns the worst state. Monitoring plugins read
+/// these lines from stdout
+fn check_thresholds(
+    out: &mut dyn Write,
+    speeds: &DeviceSpeeds,
+    args: &Cli,
+) -> std::io::Result<ThresholdState> {
+    let mut ret = ThresholdState::Ok;
+    for (ifname, speed) in speeds.iter() {
+        for (direction, rate, warn, crit) in [
+            ("rx", speed.rx, args.warn_rx, args.crit_rx),
+            ("tx", speed.tx, args.warn_tx, args.crit_tx),
+        ] {
+            let (state, limit) = match (crit, warn) {
+                (Some(crit), _) if rate > crit => (ThresholdState::Critical, crit),
+                (_, Some(warn)) if rate > warn => (ThresholdState::Warning, warn),
+                _ => continue,
+            };
+            writeln!(
+                out,
+                "{}: {} {} rate {:.0} B/s exceeds {:.0} B/s",
+                if state == ThresholdState::Critical {
+                    "CRITICAL"
+                } else {
+                    "WARNING"
+                },
+                ifname,
+                direction,
+                rate,
+                limit
+            )?;
+            ret = ret.max(state);
+        }
+    }
+    return Ok(ret);
+}
+
+/// Writes a status line and reports the critical state when any of
+/// `required` is absent
+fn check_required_interfaces(
+    out: &mut dyn Write,
+    db: &StatisticsDb,
+    required: &[String],
+) -> std::io::Result<ThresholdState> {
+    let missing: Vec<&str> = required
+        .iter()
+        .filter(|x| !db.devices.contains_key(*x))
+        .map(|x| x.as_str())
+        .collect();
+    if !missing.is_empty() {
+        writeln!(
+            out,
+            "CRITICAL: required interfaces are missing: {}",
+            missing.join(", ")
+        )?;
+        return Ok(ThresholdState::Critical);
+    }
+    return Ok(ThresholdState::Ok);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    /// Aligned table with humanized values
+    Human,
+    /// JSON object with rates in bytes per second. Each sample is written as
+    /// a single line, so watch mode produces a JSON Lines stream
+    #[value(alias = "jsonl", alias = "ndjson")]
+    Json,
+    /// Comma-separated rows with rates in bytes per second
+    Csv,
+    /// Tab-separated rows with the same fields as csv and no quoting
+    Tsv,
+    /// YAML document with the same content as json
+    Yaml,
+    /// Prometheus text format suitable for the node_exporter textfile collector
+    Prometheus,
+    /// All interfaces on one line for status bars
+    Oneline,
+}
+
+/// Modes of the program. Options shared by several modes belong to `Cli` and
+/// go before the subcommand. Running without a subcommand is the same as
+/// `live`
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Print the rates of the live counters. This is the default
+    Live,
+    /// Serve Prometheus metrics over HTTP, the same as --serve
+    Serve {
+        /// Address to listen on, e.g. 0.0.0.0:9101
+        addr: String,
+    },
+    /// Save the current counters to a file for a later diff or
+    /// --compare-snapshot and exit. The history file is not touched
+    Snapshot {
+        /// Where to write the counters
+        path: String,
+    },
+    /// Print the rates between two saved history files or snapshots without
+    /// reading the live counters. Output options go before the subcommand,
+    /// e.g. `ifstat-rs --format csv diff old.json new.json`
+    Diff {
+        /// The earlier file
+        old: String,
+        /// The later file
+        new: String,
+    },
+}
+impl Command {
+    fn get_name(&self) -> &'static str {
+        return match self {
+            Command::Live => "live",
+            Command::Serve { .. } => "serve",
+            Command::Snapshot { .. } => "snapshot",
+            Command::Diff { .. } => "diff",
+        };
+    }
+}
+
+/// Column that can be picked with --fields
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+#[value(rename_all = "snake_case")]
+enum Field {
+    /// Interface name, or its alias in the human table
+    Interface,
+    /// Receive rate in bytes per second
+    #[value(alias = "rx_bps")]
+    RxBytesPerSec,
+    /// Transmit rate in bytes per second
+    #[value(alias = "tx_bps")]
+    TxBytesPerSec,
+    #[value(alias = "rx_pps")]
+    /// Receive packets per second
+    RxPacketsPerSec,
+    #[value(alias = "tx_pps")]
+    /// Transmit packets per second
+    TxPacketsPerSec,
+    /// Receive errors per second
+    RxErrorsPerSec,
+    /// Transmit errors per second
+    TxErrorsPerSec,
+    /// Receive dropped packets per second
+    RxDropsPerSec,
+    /// Transmit dropped packets per second
+    TxDropsPerSec,
+    /// Bytes received since the interface came up
+    #[value(alias = "rx_bytes")]
+    RxTotal,
+    /// Bytes transmitted since the interface came up
+    #[value(alias = "tx_bytes")]
+    TxTotal,
+    /// Time the counters were read, RFC 3339 in csv and tsv
+    Timestamp,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum DbFormat {
+    /// Compact JSON on a single line
+    Json,
+    /// Indented JSON for inspecting the file by eye
+    JsonPretty,
+    /// Compact binary encoding of the bincode 1 crate
+    Bincode,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum CounterBitsArg {
+    #[value(name = "32")]
+    Bits32,
+    #[value(name = "64")]
+    Bits64,
+}
+impl CounterBitsArg {
+    fn get_counter_bits(self) -> CounterBits {
+        return match self {
+            CounterBitsArg::Bits32 => CounterBits::Bits32,
+            CounterBitsArg::Bits64 => CounterBits::Bits64,
+        };
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum StatisticsSource {
+    /// /proc/net/dev
+    Proc,
+    /// /sys/class/net/<iface>/statistics
+    Sysfs,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum SortKey {
+    /// Alphabetically by interface name
+    Name,
+    /// By the shown receive plus transmit rate, highest first
+    Total,
+    /// By the shown receive rate, highest first
+    Rx,
+    /// By the shown transmit rate, highest first
+    Tx,
+    /// In the order the kernel lists interfaces
+    #[value(alias = "none")]
+    Kernel,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum Units {
+    /// Bytes per second, binary prefixes by default
+    Bytes,
+    /// Bits per second, decimal prefixes by default
+    Bits,
+    /// Bytes followed by bits in parentheses
+    Both,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum ColorChoice {
+    /// Colorize only when stdout is a terminal, unless NO_COLOR or
+    /// FORCE_COLOR say otherwise
+    Auto,
+    /// Always emit ANSI color codes
+    Always,
+    /// Never colorize
+    Never,
+}
+
+/// Decides whether to colorize in order of precedence:
+/// 1. --color always or never
+/// 2. a non-empty NO_COLOR disables color
+/// 3. a FORCE_COLOR other than empty or `0` enables color
+/// 4. color only if the output is a terminal
+fn decide_color(
+    choice: ColorChoice,
+    no_color: Option<&str>,
+    force_color: Option<&str>,
+    is_terminal: bool,
+) -> bool {
+    return match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto if no_color.is_some_and(|x| !x.is_empty()) => false,
+        ColorChoice::Auto
+            if force_color.is_some_and(|x| !x.is_empty() && x != "0") =>
+        {
+            true
+        }
+        ColorChoice::Auto => is_terminal,
+    };
+}
+
+/// `to_stdout` is false when reports go to an --output file, which is never
+/// colorized automatically without FORCE_COLOR
+fn use_color(choice: ColorChoice, to_stdout: bool) -> bool {
+    return decide_color(
+        choice,
+        std::env::var("NO_COLOR").ok().as_deref(),
+        std::env::var("FORCE_COLOR").ok().as_deref(),
+        to_stdout && std::io::IsTerminal::is_terminal(&std::io::stdout()),
+    );
+}
+
+/// Shown by --version, e.g. `1.1.2 (3f2a9c1 2024-09-01)`. Set by build.rs
+const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("GIT_HASH"),
+    " ",
+    env!("BUILD_DATE"),
+    ")"
+);
+
+/// A program analogous to ifstat from iproute2 package
+/// (https://archlinux.org/packages/core/x86_64/iproute2/). Shows network device speed from
+/// /proc/net/dev. See man 5 proc
+#[derive(Debug, clap::Parser)]
+#[command(version, long_version = LONG_VERSION, about, long_about = None)]
+struct Cli {
+    /// Print diagnostics to stderr. Repeat for more detail: -v shows raw
+    /// counters and deltas, -vv debug messages, -vvv everything. Overrides
+    /// RUST_LOG
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Name of a history file. Without it and without --sample there is no
+    /// baseline, so no rates are shown. A `.gz` suffix stores it compressed
+    /// with gzip
+    #[arg(short = 'f', long)]
+    history_file: Option<String>,
+
+    /// Keep a separate history per profile by appending `.<PROFILE>` to the
+    /// history file name, or inserting it before a `.gz` suffix
+    #[arg(long, requires = "history_file")]
+    profile: Option<String>,
+
+    /// Only write the current counters to the history file as a new
+    /// baseline, without printing anything
+    #[arg(
+        long,
+        requires = "history_file",
+        conflicts_with_all = ["sample", "watch", "serve"]
+    )]
+    baseline_only: bool,
+
+    /// Print the history file path after applying --profile and exit
+    #[arg(long, requires = "history_file")]
+    print_db_path: bool,
+
+    /// Use the history file as a baseline but never update it
+    #[arg(long, conflicts_with = "baseline_only")]
+    no_write: bool,
+
+    /// Encoding of the history file when writing it. Reading detects the
+    /// format automatically
+    #[arg(long, value_enum, default_value_t = DbFormat::Json)]
+    db_format: DbFormat,
+
+    /// Save the first reading to this file as a named snapshot, independent
+    /// of the history file
+    #[arg(long, value_name = "PATH")]
+    save_snapshot: Option<String>,
+
+    /// Compare against a snapshot saved with --save-snapshot instead of the
+    /// history file. The history file is still updated
+    #[arg(long, value_name = "PATH", conflicts_with = "sample")]
+    compare_snapshot: Option<String>,
+
+    /// Print average rates since boot using the uptime as the interval
+    #[arg(long, conflicts_with_all = ["history_file", "sample", "watch", "serve"])]
+    since_boot: bool,
+
+    /// Take two readings this many seconds apart and print the rate between
+    /// them instead of using the history file as a baseline. Accepts the same
+    /// durations as --interval
+    #[arg(
+        long,
+        value_parser = parse_interval,
+        allow_hyphen_values = true,
+        conflicts_with = "watch"
+    )]
+    sample: Option<f64>,
+
+    /// Where to read interface counters from
+    #[arg(long, value_enum, default_value_t = StatisticsSource::Proc)]
+    source: StatisticsSource,
+
+    /// Read counters from this file instead of /proc/net/dev
+    #[arg(long, default_value = PROC_NET_DEV_PATH)]
+    proc_path: String,
+
+    /// Read /proc/<PID>/net/dev to watch the network namespace of this
+    /// process instead of the own one. Can be repeated. Interfaces are shown
+    /// as `<namespace inode>/<ifname>` while interface filters match the name
+    /// inside the namespace
+    #[arg(long, value_name = "PID", conflicts_with_all = ["source", "proc_path"])]
+    ns_pid: Vec<u32>,
+
+    /// /proc/net/dev receive column shown as the receive rate, for example
+    /// multicast or compressed. It is still labelled in bytes. Ignored with
+    /// --source sysfs
+    #[arg(long, value_name = "NAME", default_value = "bytes")]
+    rx_column: String,
+
+    /// /proc/net/dev transmit column shown as the transmit rate
+    #[arg(long, value_name = "NAME", default_value = "bytes")]
+    tx_column: String,
+
+    /// Date the counters by the modification time of --proc-path instead of
+    /// the current time. Useful for files captured on remote hosts, combined
+    /// with a --profile per host
+    #[arg(long, conflicts_with = "watch")]
+    use_file_mtime: bool,
+
+    /// Width of the kernel counters. 32-bit counters are expected to wrap
+    /// around, while a decrease of a 64-bit counter is treated as a reset.
+    /// Defaults to 32 on macOS, whose if_data counters are 32-bit
+    #[arg(long, value_enum, default_value_t = DEFAULT_COUNTER_BITS)]
+    counter_bits: CounterBitsArg,
+
+    /// Log and ignore malformed lines of /proc/net/dev instead of failing
+    #[arg(long)]
+    skip_bad_lines: bool,
+
+    /// Hide interfaces with zero statistics
+    #[arg(long)]
+    hide_zero_ifs: bool,
+
+    /// Hide the loopback interface
+    #[arg(long)]
+    no_loopback: bool,
+
+    /// Show only this interface. Can be repeated
+    #[arg(long, value_name = "IFNAME")]
+    interface: Vec<String>,
+
+    /// Show only interfaces whose names match this regex
+    #[arg(long, value_parser = regex::Regex::new)]
+    include: Option<regex::Regex>,
+
+    /// Hide interfaces whose names match this regex. Takes precedence over
+    /// --include
+    #[arg(long, value_parser = regex::Regex::new)]
+    exclude: Option<regex::Regex>,
+
+    /// Sum interfaces into one row named by the first capture group, e.g.
+    /// '(eth\d+)\.\d+' collapses eth0.10 and eth0.20 into eth0
+    #[arg(long, value_parser = regex::Regex::new)]
+    group: Option<regex::Regex>,
+
+    /// Display an interface under another name, e.g. enp3s0=WAN. Can be
+    /// repeated
+    #[arg(long, value_name = "IFNAME=ALIAS", value_parser = parse_alias)]
+    alias: Vec<(String, String)>,
+
+    /// Show interfaces that appeared after the baseline, rated against zero
+    /// counters
+    #[arg(long)]
+    show_new: bool,
+
+    /// Hide zeros from fields
+    #[arg(long)]
+    hide_zero_values: bool,
+
+    /// Do not print interfaces whose receive plus transmit rate in bytes per
+    /// second is below this. Accepts suffixes like 1K or 10MiB
+    #[arg(long, value_name = "SIZE", value_parser = parse_human_size)]
+    min_rate: Option<f64>,
+
+    /// How to order interfaces
+    #[arg(long, value_enum, default_value_t = SortKey::Name)]
+    sort: SortKey,
+
+    /// Sort devices by total statistics instead of alphabetically. Same as
+    /// --sort total
+    #[arg(long, conflicts_with = "sort")]
+    sort_by_stat: bool,
+
+    /// Reverse the sort order
+    #[arg(long)]
+    sort_reverse: bool,
+
+    /// Show only the first N interfaces of the sorted table. The total row
+    /// still sums all of them
+    #[arg(long)]
+    top: Option<usize>,
+
+    /// Show packets per second in addition to bytes
+    #[arg(long)]
+    show_packets: bool,
+
+    /// Show errors and drops per second
+    #[arg(long)]
+    show_errors: bool,
+
+    /// Show rates as a percentage of the link speed from
+    /// /sys/class/net/<iface>/speed
+    #[arg(long)]
+    show_utilization: bool,
+
+    /// Show the average packet size per direction, i.e. the byte delta over
+    /// the packet delta. Small sizes at high packet rates point at a flood
+    #[arg(long)]
+    show_avg_pktsize: bool,
+
+    /// Warn when a rate is higher than the link speed times this factor,
+    /// which usually means a counter reset or a parsing bug. Interfaces
+    /// without a known link speed are not checked
+    #[arg(long, value_name = "FACTOR", value_parser = parse_anomaly_margin)]
+    check_link_speed: Option<f64>,
+
+    /// Show cumulative bytes received and transmitted since boot
+    #[arg(long)]
+    show_total_bytes: bool,
+
+    /// Show bytes received and transmitted between the two snapshots, not
+    /// divided by the interval
+    #[arg(long)]
+    show_delta: bool,
+
+    /// Print the slaves of bonding interfaces below their master, followed by
+    /// a row with the sum over the slaves
+    #[arg(long)]
+    bond_detail: bool,
+
+    /// Mark each receive and transmit rate with an arrow showing whether it
+    /// rose, fell or stayed steady since the previous sample
+    #[arg(long, requires = "watch")]
+    trend: bool,
+
+    /// Rate changes up to this many bytes per second are shown as steady by
+    /// --trend. Accepts suffixes like 1K
+    #[arg(long, value_name = "SIZE", default_value = "1K", value_parser = parse_human_size)]
+    trend_threshold: f64,
+
+    /// Add columns with a recent peak of the receive and transmit rates. The
+    /// peak follows rising rates at once and falls slowly afterwards
+    #[arg(long, requires = "watch")]
+    peak_hold: bool,
+
+    /// Factor the --peak-hold peak is multiplied by every sample before it is
+    /// compared with the current rate. Lower values fall faster
+    #[arg(long, value_name = "FACTOR", default_value_t = 0.9, value_parser = parse_decay)]
+    peak_decay: f64,
+
+    /// Print a row with the sum over all shown interfaces
+    #[arg(long)]
+    total: bool,
+
+    /// Print `iface rx rx_units tx tx_units` separated by single spaces with
+    /// no header and no alignment, for narrow terminals and serial consoles
+    #[arg(long)]
+    flat: bool,
+
+    /// Print only a single line with the summed receive and transmit rates of
+    /// all shown interfaces instead of the table
+    #[arg(long, conflicts_with = "total")]
+    totals_only: bool,
+
+    /// Add a column with the sum of receive and transmit rates
+    #[arg(long)]
+    combined: bool,
+
+    /// Which units to show rates in
+    #[arg(long, value_enum, default_value_t = Units::Both)]
+    units: Units,
+
+    /// Drop the bits per second in parentheses and only show bytes. Same as
+    /// --units bytes
+    #[arg(long, conflicts_with = "units")]
+    no_bits: bool,
+
+    /// Use 1024-based Ki/Mi prefixes for both bytes and bits
+    #[arg(long, conflicts_with = "decimal")]
+    binary: bool,
+
+    /// Use 1000-based K/M prefixes for bytes too. Bits are always decimal
+    /// unless --binary is given
+    #[arg(long)]
+    decimal: bool,
+
+    /// Prefixes of byte rates and totals. Defaults to iec
+    #[arg(long, value_enum, conflicts_with_all = ["binary", "decimal"])]
+    byte_prefixes: Option<PrefixSystem>,
+
+    /// Prefixes of bit rates. Defaults to si
+    #[arg(long, value_enum, conflicts_with = "binary")]
+    bit_prefixes: Option<PrefixSystem>,
+
+    /// Print plain rates without scaling them to Ki/Mi/K/M prefixes
+    #[arg(long)]
+    raw: bool,
+
+    /// Number of digits after the decimal point
+    #[arg(
+        long,
+        default_value_t = 2,
+        value_parser = clap::value_parser!(u8).range(0..=10)
+    )]
+    precision: u8,
+
+    /// Width of the rate columns. By default fits the widest value
+    #[arg(long)]
+    column_width: Option<usize>,
+
+    /// Width of the interface column. Longer names are cut with an ellipsis.
+    /// By default fits the longest name, which makes the columns move when
+    /// interfaces come and go in watch mode
+    #[arg(long)]
+    name_width: Option<usize>,
+
+    /// When to colorize rates by magnitude. With auto the NO_COLOR and
+    /// FORCE_COLOR environment variables are honored, NO_COLOR taking
+    /// precedence
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Print the time of the poll above the table
+    #[arg(long)]
+    timestamp: bool,
+
+    /// strftime-like format of --timestamp. ISO 8601 by default
+    #[arg(long, requires = "timestamp", value_parser = parse_time_format)]
+    time_format: Option<String>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+
+    /// Write reports to this file instead of stdout. The file is truncated on
+    /// start unless --append is given. A FIFO must already have a reader, and
+    /// the program exits when the reader goes away
+    #[arg(short = 'o', long, value_name = "PATH")]
+    output: Option<String>,
+
+    /// Append to the --output file instead of truncating it
+    #[arg(long, requires = "output")]
+    append: bool,
+
+    /// Indent the json format. Samples then span several lines, so watch mode
+    /// no longer produces JSON Lines. Use --db-format json-pretty for the
+    /// history file
+    #[arg(long)]
+    json_pretty: bool,
+
+    /// Direction markers of the oneline format
+    #[arg(long, value_enum, default_value_t = OnelineLabels::Arrows)]
+    oneline_labels: OnelineLabels,
+
+    /// Print only these comma-separated columns in this order, e.g.
+    /// `interface,rx_bps,tx_bps,rx_total`. Works with the human, csv and tsv
+    /// formats
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "FIELDS")]
+    fields: Vec<Field>,
+
+    /// Do not print the header row and the interval footer of the human table,
+    /// or the header row of the csv and tsv formats
+    #[arg(long)]
+    no_header: bool,
+
+    /// Also send one syslog entry with the rates in bytes per second per
+    /// sample
+    #[cfg(unix)]
+    #[arg(long)]
+    syslog: bool,
+
+    /// Syslog facility of the entries
+    #[cfg(unix)]
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SyslogFacility::User,
+        requires = "syslog"
+    )]
+    syslog_facility: SyslogFacility,
+
+    /// Syslog tag of the entries
+    #[cfg(unix)]
+    #[arg(long, default_value = "ifstat-rs", requires = "syslog")]
+    syslog_tag: String,
+
+    /// Serve Prometheus metrics over HTTP on this address, e.g. 0.0.0.0:9101.
+    /// Rates are computed against the previous scrape
+    #[arg(long, conflicts_with_all = ["watch", "sample"])]
+    serve: Option<String>,
+
+    /// Keep running and reprint the table every interval
+    #[arg(short = 'w', long)]
+    watch: bool,
+
+    /// Exit with code 2 when this interface is absent. Can be repeated
+    #[arg(long, value_name = "IFNAME")]
+    require: Vec<String>,
+
+    /// Exit with code 1 when a receive rate in bytes per second exceeds this.
+    /// Accepts suffixes like 100M or 1GiB
+    #[arg(long, value_parser = parse_human_size)]
+    warn_rx: Option<f64>,
+
+    /// Exit with code 2 when a receive rate exceeds this
+    #[arg(long, value_parser = parse_human_size)]
+    crit_rx: Option<f64>,
+
+    /// Exit with code 1 when a transmit rate exceeds this
+    #[arg(long, value_parser = parse_human_size)]
+    warn_tx: Option<f64>,
+
+    /// Exit with code 2 when a transmit rate exceeds this
+    #[arg(long, value_parser = parse_human_size)]
+    crit_tx: Option<f64>,
+
+    /// Exit watch mode after printing this many samples
+    #[arg(
+        short = 'c',
+        long,
+        requires = "watch",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    count: Option<u64>,
+
+    /// Show the mean rate over the last N samples of watch mode
+    #[arg(
+        long,
+        requires = "watch",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    average: Option<u32>,
+
+    /// On exit from watch mode print a table of the highest rates seen per
+    /// interface
+    #[arg(long, requires = "watch")]
+    show_peak: bool,
+
+    /// On exit from watch mode, by Ctrl-C or --count, print the bytes
+    /// transferred per interface over the whole session with the average
+    /// and peak rates
+    #[arg(long, requires = "watch")]
+    summary: bool,
+
+    /// Polling interval in seconds for watch mode. Fractions like 0.5,
+    /// suffixes like 5m or 1h and ISO 8601 durations like PT30S are allowed
+    #[arg(
+        short = 'i',
+        long,
+        default_value_t = 1_f64,
+        value_parser = parse_interval,
+        allow_hyphen_values = true
+    )]
+    interval: f64,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Cli {
+    /// Rejects flags that a subcommand would silently ignore. Live is the
+    /// default mode and only excludes serving
+    fn check_mode_flags(&self, command: &Command) -> anyhow::Result<()> {
+        let live = matches!(command, Command::Live);
+        let flags = [
+            ("--watch", self.watch, live),
+            ("--baseline-only", self.baseline_only, live),
+            ("--serve", self.serve.is_some(), false),
+            ("--sample", self.sample.is_some(), live),
+            ("--compare-snapshot", self.compare_snapshot.is_some(), live),
+            ("--group", self.group.is_some(), live),
+            ("--min-rate", self.min_rate.is_some(), live),
+        ];
+        for (flag, set, allowed) in flags {
+            if set && !allowed {
+                return Err(anyhow::anyhow!(
+                    "{flag} cannot be combined with the {} subcommand",
+                    command.get_name()
+                ));
+            }
+        }
+        return Ok(());
+    }
+
+    fn get_sort_key(&self) -> SortKey {
+        if self.sort_by_stat {
+            return SortKey::Total;
+        }
+        return self.sort;
+    }
+
+    fn get_history_file(&self) -> Option<String> {
+        let history_file = self.history_file.as_ref()?;
+        // The profile goes before `.gz` so the file is still compressed
+        return Some(match &self.profile {
+            Some(profile) => match history_file.strip_suffix(".gz") {
+                Some(stem) => format!("{}.{}.gz", stem, profile),
+                None => format!("{}.{}", history_file, profile),
+            },
+            None => history_file.clone(),
+        });
+    }
+
+    fn get_table_options(&self) -> TableOptions {
+        return TableOptions {
+            hide_zero_values: self.hide_zero_values,
+            sort_key: self.get_sort_key(),
+            sort_direction: self.get_sort_direction(),
+            top: self.top,
+            show_packets: self.show_packets,
+            show_errors: self.show_errors,
+            show_total: self.total,
+            totals_only: self.totals_only,
+            flat: self.flat,
+            show_combined: self.combined,
+            show_utilization: self.show_utilization,
+            show_total_bytes: self.show_total_bytes,
+            show_delta: self.show_delta,
+            show_avg_pktsize: self.show_avg_pktsize,
+            bond_detail: self.bond_detail,
+            trend: self.trend,
+            trend_threshold: self.trend_threshold,
+            peak_hold: self.peak_hold,
+            color: use_color(self.color, self.output.is_none()),
+            units: if self.no_bits {
+                Units::Bytes
+            } else {
+                self.units
+            },
+            byte_prefixes: self.byte_prefixes.unwrap_or(if self.decimal {
+                PrefixSystem::Decimal
+            } else {
+                PrefixSystem::Binary
+            }),
+            bit_prefixes: self.bit_prefixes.unwrap_or(if self.binary {
+                PrefixSystem::Binary
+            } else {
+                PrefixSystem::Decimal
+            }),
+            column_width: self.column_width,
+            name_width: self.name_width,
+            raw: self.raw,
+            precision: self.precision.into(),
+            no_header: self.no_header,
+            aliases: self.alias.iter().cloned().collect(),
+        };
+    }
+
+    fn get_sort_direction(&self) -> SortDirection {
+        if self.sort_reverse {
+            return SortDirection::Reversed;
+        }
+        return SortDirection::Natural;
+    }
+}
+
+fn format_timestamp(
+    timestamp: &chrono::DateTime<chrono::Utc>,
+    time_format: Option<&str>,
+) -> String {
+    let local = timestamp.with_timezone(&chrono::Local);
+    return match time_format {
+        Some(f) => local.format(f).to_string(),
+        None => local.to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+    };
+}
+
+const PROC_UPTIME_PATH: &str = "/proc/uptime";
+
+/// Seconds since boot, the first field of /proc/uptime
+fn read_uptime() -> anyhow::Result<f64> {
+    let content = std::fs::read_to_string(PROC_UPTIME_PATH)
+        .with_context(|| format!("Failed to read {}", PROC_UPTIME_PATH))?;
+    return content
+        .split_ascii_whitespace()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("{} is empty", PROC_UPTIME_PATH))?
+        .parse::<f64>()
+        .with_context(|| format!("Failed to parse {}", PROC_UPTIME_PATH));
+}
+
+/// Returns counter deltas against the previous reading and the interval
+/// between both in seconds. Without a previous reading the cumulative counters
+/// are returned with a zero interval
+fn compute_diff(
+    prev: Option<&StatisticsDb>,
+    current: &StatisticsDb,
+    args: &Cli,
+) -> anyhow::Result<(DeviceRates, f64)> {
+    return Ok(match prev {
+        Some(a) if a.timestamp > current.timestamp => {
+            log::warn!(
+                "Baseline timestamp {} is in the future, the clock probably \
+                 jumped backward. Treating this run as a fresh snapshot",
+                a.timestamp.to_rfc3339()
+            );
+            (current.devices.clone(), 0_f64)
+        }
+        Some(a) => {
+            let diff = subtract_device_rates(
+                &current.devices,
+                &a.devices,
+                args.counter_bits.get_counter_bits(),
+                args.show_new,
+            );
+            let interval = (current.timestamp - a.timestamp)
+                .to_std()
+                .context("Duration is negative!")?
+                .as_secs_f64();
+            log::debug!("Interval = {} s", interval);
+            (diff, interval)
+        }
+        None if args.since_boot => (current.devices.clone(), read_uptime()?),
+        None => (current.devices.clone(), 0_f64),
+    });
+}
+
+/// Prints rates in the requested format. `speeds` are the rates of `diff`,
+/// `averaged` replaces them in the human table when --average is active,
+/// `previous` are the rates shown in the previous sample and `held` the peaks
+/// of --peak-hold. `options` are built once from `args` so watch mode does
+/// not rebuild them on every sample
+#[allow(clippy::too_many_arguments)]
+fn print_stats(
+    out: &mut dyn Write,
+    diff: &DeviceRates,
+    speeds: &DeviceSpeeds,
+    interval: f64,
+    current: &StatisticsDb,
+    averaged: Option<&DeviceSpeeds>,
+    previous: Option<&DeviceSpeeds>,
+    held: Option<&DeviceSpeeds>,
+    args: &Cli,
+    options: &TableOptions,
+) -> anyhow::Result<()> {
+    match args.format {
+        OutputFormat::Human => {
+            if args.timestamp {
+                writeln!(
+                    out,
+                    "{}",
+                    format_timestamp(
+                        &current.timestamp,
+                        args.time_format.as_deref()
+                    )
+                )?;
+            }
+            match averaged {
+                _ if !args.fields.is_empty() => pretty_print_fields_table(
+                    out,
+                    averaged.unwrap_or(speeds),
+                    current,
+                    &args.fields,
+                    options,
+                )?,
+                Some(averaged) => pretty_print_speed_table(
+                    out,
+                    averaged,
+                    Some(diff),
+                    previous,
+                    held,
+                    current,
+                    options,
+                )?,
+                None => pretty_print_devices_speed(
+                    out, speeds, diff, current, interval, previous, held, options,
+                )?,
+            }
+        }
+        OutputFormat::Json => {
+            print_json_report(out, speeds, current, interval, args.json_pretty)?
+        }
+        OutputFormat::Yaml => print_yaml_report(out, speeds, current, interval)?,
+        OutputFormat::Csv | OutputFormat::Tsv => print_delimited_report(
+            out,
+            speeds,
+            current,
+            args.get_sort_key(),
+            args.get_sort_direction(),
+            args.no_header,
+            if args.format == OutputFormat::Csv {
+                Delimiter::Comma
+            } else {
+                Delimiter::Tab
+            },
+            &args.fields,
+        )?,
+        OutputFormat::Prometheus => write!(
+            out,
+            "{}",
+            format_prometheus_report(
+                speeds,
+                current,
+                interval,
+                args.get_sort_key(),
+                args.get_sort_direction(),
+            )
+        )?,
+        OutputFormat::Oneline => writeln!(
+            out,
+            "{}",
+            format_oneline_report(speeds, current, options, args.oneline_labels,)
+        )?,
+    }
+    return Ok(());
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn get_file_mtime(path: &str) -> anyhow::Result<chrono::DateTime<chrono::Utc>> {
+    let modified = std::fs::metadata(path)
+        .and_then(|x| x.modified())
+        .with_context(|| format!("Failed to get modification time of {}", path))?;
+    return Ok(modified.into());
+}
+
+/// Inode number identifying the network namespace of a process, from the
+/// `net:[4026531833]` link of /proc/<pid>/ns/net
+#[cfg(not(any(windows, target_os = "macos")))]
+fn get_namespace_id(pid: u32) -> anyhow::Result<String> {
+    let path = format!("/proc/{pid}/ns/net");
+    let target = std::fs::read_link(&path)
+        .with_context(|| format!("Failed to read link {}", path))?;
+    let target = target.to_string_lossy();
+    return target
+        .strip_prefix("net:[")
+        .and_then(|x| x.strip_suffix(']'))
+        .map(|x| x.to_string())
+        .ok_or_else(|| anyhow::anyhow!("Unexpected namespace link {}", target));
+}
+
+/// Counters of the namespaces of the --ns-pid processes merged into one db.
+/// Names are prefixed with the namespace so the history file keeps equally
+/// named interfaces of different namespaces apart. Processes sharing a
+/// namespace are read once
+#[cfg(not(any(windows, target_os = "macos")))]
+fn parse_namespaces(
+    pids: &[u32],
+    args: &Cli,
+    filter: &InterfaceFilter,
+) -> anyhow::Result<StatisticsDb> {
+    let mut ret = StatisticsDb::new();
+    let mut seen = std::collections::BTreeSet::new();
+    for pid in pids {
+        let namespace = get_namespace_id(*pid).with_context(|| {
+            format!("Failed to get the network namespace of process {}", pid)
+        })?;
+        if !seen.insert(namespace.clone()) {
+            log::debug!("Process {pid} shares namespace {namespace}. Skipping");
+            continue;
+        }
+        let path = format!("/proc/{pid}/net/dev");
+        let db = parse_proc_net_dev(
+            &path,
+            args.hide_zero_ifs,
+            args.skip_bad_lines,
+            filter,
+            &args.rx_column,
+            &args.tx_column,
+        )
+        .with_context(|| format!("Failed to parse {} file", path))?;
+        ret.timestamp = ret.timestamp.max(db.timestamp);
+        for ifname in db.kernel_order().iter() {
+            if let Some(stat) = db.devices.get(ifname) {
+                ret.insert(format!("{namespace}/{ifname}"), *stat);
+            }
+        }
+    }
+    return Ok(ret);
+}
+
+/// Reads current counters with the backend of the target platform. --source
+/// only applies to Linux
+fn parse_network_stats(
+    args: &Cli,
+    filter: &InterfaceFilter,
+) -> anyhow::Result<StatisticsDb> {
+    #[cfg(windows)]
+    return windows::parse_if_table(args.hide_zero_ifs, filter)
+        .context("Failed to read the interface table");
+    #[cfg(target_os = "macos")]
+    return macos::parse_ifaddrs(args.hide_zero_ifs, filter)
+        .context("Failed to read interface addresses");
+    #[cfg(not(any(windows, target_os = "macos")))]
+    return match args.source {
+        StatisticsSource::Proc if !args.ns_pid.is_empty() => {
+            parse_namespaces(&args.ns_pid, args, filter)
+        }
+        StatisticsSource::Proc => {
+            let mut ret = parse_proc_net_dev(
+                &args.proc_path,
+                args.hide_zero_ifs,
+                args.skip_bad_lines,
+                filter,
+                &args.rx_column,
+                &args.tx_column,
+            )
+            .with_context(|| format!("Failed to parse {} file", args.proc_path))?;
+            if args.use_file_mtime {
+                ret.timestamp = get_file_mtime(&args.proc_path)?;
+            }
+            Ok(ret)
+        }
+        StatisticsSource::Sysfs => parse_sysfs(args.hide_zero_ifs, filter)
+            .with_context(|| {
+                format!("Failed to parse {} directory", SYS_CLASS_NET_PATH)
+            }),
+    };
+}
+
+/// Warns about rates above `margin` times the link speed for
+/// --check-link-speed. Such rates are impossible on real hardware
+fn check_link_speed_anomalies(diff: &DeviceRates, seconds: f64, margin: f64) {
+    for (ifname, stat) in diff.iter() {
+        let link_speed = match read_link_speed(ifname) {
+            Some(x) => x,
+            None => continue,
+        };
+        let speed = DeviceSpeed::new(stat, seconds);
+        for (direction, rate) in [("receive", speed.rx), ("transmit", speed.tx)] {
+            let bits = rate * 8_f64;
+            if bits > link_speed * margin {
+                log::warn!(
+                    "{ifname}: {direction} rate of {:.0} bit/s is above the link \
+                     speed of {:.0} bit/s. The counters were probably reset",
+                    bits,
+                    link_speed
+                );
+            }
+        }
+    }
+}
+
+/// Logs raw cumulative counters and deltas of every interface for -v
+fn log_counter_details(current: &StatisticsDb, diff: &DeviceRates) {
+    for (ifname, stat) in current.devices.iter() {
+        let delta = diff.get(ifname).copied().unwrap_or_default();
+        log::info!(
+            "{ifname}: counters rx={} tx={} rx_packets={} tx_packets={}, \
+             delta rx={} tx={} rx_packets={} tx_packets={}",
+            stat.rx,
+            stat.tx,
+            stat.rx_packets,
+            stat.tx_packets,
+            delta.rx,
+            delta.tx,
+            delta.rx_packets,
+            delta.tx_packets
+        );
+    }
+}
+
+/// Rust ignores SIGPIPE, so a closed stdout like `| head` shows up as a
+/// BrokenPipe error from any write. It ends the program as successfully as
+/// the default signal action would, without an error message
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    return match run() {
+        Err(e) if is_broken_pipe(&e) => {
+            log::debug!("Output was closed by the reader. Exiting");
+            Ok(())
+        }
+        result => Ok(result?),
+    };
+}
+
+/// Writer for --output or stdout
+fn get_output(args: &Cli) -> anyhow::Result<Box<dyn Write>> {
+    return Ok(match &args.output {
+        Some(path) => Box::new(std::io::BufWriter::new(open_output_file(
+            path,
+            args.append,
+        )?)),
+        None => Box::new(std::io::stdout().lock()),
+    });
+}
+
+/// The diff subcommand. Rates are taken over the time between the timestamps
+/// of both files
+fn print_offline_diff(
+    old: &str,
+    new: &str,
+    filter: &InterfaceFilter,
+    args: &Cli,
+) -> anyhow::Result<()> {
+    let read = |path: &str| -> anyhow::Result<StatisticsDb> {
+        let mut ret = parse_stat_db(path)
+            .with_context(|| format!("Failed to read snapshot {}", path))?;
+        ret.retain(|x| filter.is_selected(x));
+        return Ok(ret);
+    };
+    let (a, b) = (read(old)?, read(new)?);
+    if a.timestamp > b.timestamp {
+        return Err(anyhow::anyhow!(
+            "`{}` was saved before `{}`. Swap the arguments",
+            new,
+            old
+        ));
+    }
+    let (diff, interval) = compute_diff(Some(&a), &b, args)?;
+    let mut out = get_output(args)?;
+    print_stats(
+        &mut out,
+        &diff,
+        &get_device_speeds(&diff, interval),
+        interval,
+        &b,
+        None,
+        None,
+        None,
+        args,
+        &args.get_table_options(),
+    )?;
+    out.flush()?;
+    return Ok(());
+}
+
+fn run() -> anyhow::Result<()> {
+    use clap::Parser;
+    let args = Cli::parse();
+
+    let mut logger = env_logger::Builder::from_default_env();
+    match args.verbose {
+        0 => &mut logger,
+        1 => logger.filter_level(log::LevelFilter::Info),
+        2 => logger.filter_level(log::LevelFilter::Debug),
+        _ => logger.filter_level(log::LevelFilter::Trace),
+    }
+    .init();
+
+    let filter = InterfaceFilter {
+        names: args.interface.clone(),
+        no_loopback: args.no_loopback,
+        include: args.include.clone(),
+        exclude: args.exclude.clone(),
+    };
+    let read_current_stats = || {
+        let ret = parse_network_stats(&args, &filter);
+        if let Ok(db) = &ret {
+            for ifname in args.interface.iter() {
+                if !db.devices.contains_key(ifname) {
+                    log::warn!("Requested interface {ifname} was not found");
+                }
+            }
+        }
+        return ret;
+    };
+    if !args.fields.is_empty()
+        && !matches!(
+            args.format,
+            OutputFormat::Human | OutputFormat::Csv | OutputFormat::Tsv
+        )
+    {
+        return Err(anyhow::anyhow!(
+            "--fields is only supported by the human, csv and tsv formats"
+        ));
+    }
+    if let Some(command) = &args.command {
+        args.check_mode_flags(command)?;
+    }
+    let serve_addr = match &args.command {
+        Some(Command::Live) | None => args.serve.as_ref(),
+        Some(Command::Serve { addr }) => Some(addr),
+        Some(Command::Snapshot { path }) => {
+            dump_stat_db(path, &read_current_stats()?, args.db_format)
+                .context("Failed to save snapshot")?;
+            log::debug!("Saved a snapshot to `{}`", path);
+            return Ok(());
+        }
+        Some(Command::Diff { old, new }) => {
+            return print_offline_diff(old, new, &filter, &args);
+        }
+    };
+    let history_file = args.get_history_file();
+    if let Some(history_file) = &history_file {
+        log::info!("Using history file `{}`", history_file);
+    }
+    if args.print_db_path {
+        let history_file = history_file
+            .ok_or_else(|| anyhow::anyhow!("No history file is configured"))?;
+        writeln!(std::io::stdout(), "{}", history_file)?;
+        return Ok(());
+    }
+    let written_history_file = if args.no_write {
+        None
+    } else {
+        history_file.as_ref()
+    };
+    if args.baseline_only {
+        if let Some(history_file) = &history_file {
+            dump_stat_db(history_file, &read_current_stats()?, args.db_format)
+                .context("Failed to update statistics db")?;
+            log::debug!("Wrote a new baseline to `{}`", history_file);
+        }
+        return Ok(());
+    }
+    if let Some(addr) = serve_addr {
+        serve_metrics(addr, &read_current_stats, &args)?;
+        return Ok(());
+    }
+    let mut prev = if let Some(sample) = args.sample {
+        let first = read_current_stats()?;
+        log::debug!("Sampling for {} s", sample);
+        std::thread::sleep(std::time::Duration::from_secs_f64(sample));
+        Some(first)
+    } else if let Some(snapshot) = &args.compare_snapshot {
+        Some(
+            parse_stat_db(snapshot)
+                .with_context(|| format!("Failed to read snapshot {}", snapshot))?,
+        )
+    } else if let Some(history_file) = &history_file {
+        if is_file_exist(history_file) {
+            log::debug!("File `{}` exists", history_file);
+            Some(parse_stat_db(history_file)?)
+        } else {
+            log::debug!("File `{}` does not exist", history_file);
+            None
+        }
+    } else {
+        None
+    };
+    let mut out = get_output(&args)?;
+    if args.watch {
+        install_sigint_handler()?;
+    }
+    let clear = args.watch
+        && args.format == OutputFormat::Human
+        && args.output.is_none()
+        && std::io::IsTerminal::is_terminal(&std::io::stdout());
+    let options = args.get_table_options();
+    let mut peaks = DeviceSpeeds::new();
+    let mut session_totals = std::collections::BTreeMap::new();
+    let mut peak_hold = args.peak_hold.then(|| PeakHold::new(args.peak_decay));
+    let mut averager = args.average.map(|x| RateAverager::new(x as usize));
+    // Previous sample of watch mode. Unlike prev it is never a fixed snapshot
+    let mut last_devices: Option<DeviceRates> = None;
+    let mut last_speeds: Option<DeviceSpeeds> = None;
+    let mut save_snapshot = args.save_snapshot.as_ref();
+    let mut samples = 0_u64;
+    let mut threshold_state = ThresholdState::Ok;
+    #[cfg(unix)]
+    let mut syslog = if args.syslog {
+        Some(SyslogWriter::connect(
+            args.syslog_facility,
+            &args.syslog_tag,
+        )?)
+    } else {
+        None
+    };
+    let last_shown = loop {
+        // A failing first read is rather a wrong --proc-path than a hiccup
+        let current = if args.watch && samples > 0 {
+            read_with_retry(&read_current_stats)?
+        } else {
+            read_current_stats()?
+        };
+        if let Some(previous) =
+            last_devices.as_ref().or(prev.as_ref().map(|x| &x.devices))
+        {
+            log_interface_changes(previous, &current.devices);
+        }
+        if args.watch {
+            last_devices = Some(current.devices.clone());
+        }
+        if let Some(history_file) = written_history_file {
+            dump_stat_db(history_file, &current, args.db_format)
+                .context("Failed to update statistics db")?;
+        }
+        if let Some(snapshot) = save_snapshot.take() {
+            dump_stat_db(snapshot, &current, args.db_format)
+                .context("Failed to save snapshot")?;
+        }
+        if clear {
+            clear_screen(&mut out)?;
+        }
+        threshold_state = threshold_state.max(check_required_interfaces(
+            &mut out,
+            &current,
+            &args.require,
+        )?);
+        let (diff, interval) = compute_diff(prev.as_ref(), &current, &args)?;
+        if args.verbose > 0 {
+            log_counter_details(&current, &diff);
+        }
+        if let Some(margin) = args.check_link_speed {
+            if interval > 0_f64 {
+                check_link_speed_anomalies(&diff, interval, margin);
+            }
+        }
+        let (diff, grouped) = match &args.group {
+            Some(group) => (
+                group_device_rates(&diff, group),
+                Some(group_statistics_db(&current, group)),
+            ),
+            None => (diff, None),
+        };
+        let shown = grouped.as_ref().unwrap_or(&current);
+        let speeds = get_device_speeds(&diff, interval);
+        let mut averaged = match &mut averager {
+            Some(averager) if prev.is_some() && interval > 0_f64 => {
+                averager.push(&diff, interval);
+                Some(averager.get_speeds())
+            }
+            _ => None,
+        };
+        let fast = args
+            .min_rate
+            .map(|min_rate| filter_min_rate(&diff, interval, min_rate));
+        if let (Some(speeds), Some(min_rate)) = (&mut averaged, args.min_rate) {
+            speeds.retain(|_, x| x.rx + x.tx >= min_rate);
+        }
+        let printed = fast.as_ref().unwrap_or(&diff);
+        let printed_speeds = match &fast {
+            Some(fast) => std::borrow::Cow::Owned(
+                speeds
+                    .iter()
+                    .filter(|(k, _)| fast.contains_key(*k))
+                    .map(|(k, v)| (k.clone(), *v))
+                    .collect(),
+            ),
+            None => std::borrow::Cow::Borrowed(&speeds),
+        };
+        let held = match &mut peak_hold {
+            Some(peak_hold) if prev.is_some() && interval > 0_f64 => {
+                peak_hold.update(&speeds);
+                Some(&peak_hold.peaks)
+            }
+            _ => None,
+        };
+        print_stats(
+            &mut out,
+            printed,
+            &printed_speeds,
+            interval,
+            shown,
+            averaged.as_ref(),
+            last_speeds.as_ref(),
+            held,
+            &args,
+            &options,
+        )?;
+        out.flush()?;
+        #[cfg(unix)]
+        if let Some(syslog) = &mut syslog {
+            if prev.is_some() {
+                let sent = syslog.send(&format_syslog_report(
+                    &printed_speeds,
+                    shown,
+                    args.get_sort_key(),
+                    args.get_sort_direction(),
+                ));
+                // E.g. journald restarting must not end a watch session
+                if let Err(e) = sent {
+                    log::warn!("{e:#}");
+                }
+            }
+        }
+        if interval > 0_f64 {
+            threshold_state =
+                threshold_state.max(check_thresholds(&mut out, &speeds, &args)?);
+        }
+        if args.show_peak && prev.is_some() {
+            update_peak_speeds(&mut peaks, &speeds, interval);
+        }
+        if args.trend && interval > 0_f64 {
+            last_speeds = Some(match averaged {
+                Some(x) => x,
+                None => printed_speeds.into_owned(),
+            });
+        }
+        if args.summary && prev.is_some() && interval > 0_f64 {
+            update_session_totals(&mut session_totals, &diff, interval);
+        }
+        samples += 1;
+        if !args.watch
+            || args.count.is_some_and(|x| samples >= x)
+            || !sleep_interruptible(std::time::Duration::from_secs_f64(
+                args.interval,
+            ))
+        {
+            break grouped.unwrap_or(current);
+        }
+        // A compared snapshot stays the baseline of every watch sample
+        if args.compare_snapshot.is_none() {
+            prev = Some(current);
+        }
+    };
+    if args.show_peak {
+        writeln!(out, "Peak rates")?;
+        pretty_print_speed_table(
+            &mut out,
+            &peaks,
+            None,
+            None,
+            None,
+            &last_shown,
+            &options,
+        )?;
+    }
+    if args.summary && !session_totals.is_empty() {
+        writeln!(out, "Session summary")?;
+        pretty_print_session_summary(
+            &mut out,
+            &session_totals,
+            &last_shown,
+            &options,
+        )?;
+    }
+    out.flush()?;
+    if threshold_state != ThresholdState::Ok {
+        std::process::exit(threshold_state as i32);
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn speed(rx: f64, tx: f64) -> DeviceSpeed {
+        return DeviceSpeed {
+            rx,
+            tx,
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn sorts_by_shown_rates() {
+        let mut db = StatisticsDb::new();
+        // The idle interface has the larger history
+        db.insert(
+            "idle".to_string(),
+            DeviceStatistics {
+                rx: 1 << 40,
+                tx: 1 << 40,
+                ..Default::default()
+            },
+        );
+        db.insert("busy".to_string(), DeviceStatistics::default());
+        let speeds = DeviceSpeeds::from([
+            ("idle".to_string(), speed(0_f64, 0_f64)),
+            ("busy".to_string(), speed(1e6, 1e6)),
+        ]);
+        let sorted = get_sorted_ifs(
+            &speeds,
+            &db,
+            SortKey::Total,
+            SortDirection::Natural,
+            &InterfaceAliases::new(),
+        );
+        assert_eq!(sorted, ["busy", "idle"]);
+    }
+
+    #[test]
+    fn table_without_interval_shows_placeholder() {
+        let options = Cli::parse_from(["ifstat-rs"]).get_table_options();
+        let mut db = StatisticsDb::new();
+        db.insert(
+            "eth0".to_string(),
+            DeviceStatistics {
+                rx: 1000,
+                tx: 2000,
+                ..Default::default()
+            },
+        );
+        let mut out = Vec::new();
+        pretty_print_devices_speed(
+            &mut out,
+            &get_device_speeds(&db.devices, 0_f64),
+            &db.devices,
+            &db,
+            0_f64,
+            None,
+            None,
+            &options,
+        )
+        .unwrap();
+        let table = String::from_utf8(out).unwrap();
+        assert!(table.contains("eth0"));
+        assert!(table.contains(NO_RATE_PLACEHOLDER));
+        assert!(!table.contains("NaN") && !table.contains("inf"));
+    }
+
+    #[test]
+    fn rejects_mode_flags_with_subcommands() {
+        let check = |argv: &[&str]| -> bool {
+            let args = Cli::parse_from(argv);
+            return args
+                .check_mode_flags(args.command.as_ref().unwrap())
+                .is_ok();
+        };
+        assert!(check(&["ifstat-rs", "-w", "live"]));
+        assert!(!check(&["ifstat-rs", "--serve", "[::]:9101", "live"]));
+        assert!(!check(&["ifstat-rs", "-w", "-c", "3", "diff", "a", "b"]));
+        assert!(!check(&[
+            "ifstat-rs",
+            "-f",
+            "x",
+            "--baseline-only",
+            "snapshot",
+            "y"
+        ]));
+        assert!(check(&["ifstat-rs", "--format", "csv", "diff", "a", "b"]));
+        assert!(!check(&["ifstat-rs", "--group", "(eth)", "diff", "a", "b"]));
+        assert!(!check(&["ifstat-rs", "--min-rate", "1k", "diff", "a", "b"]));
+        assert!(check(&["ifstat-rs", "--min-rate", "1k", "live"]));
+    }
+
+    #[test]
+    fn color_decision_table() {
+        use ColorChoice::{Always, Auto, Never};
+        #[rustfmt::skip]
+        let cases = [
+            // choice, NO_COLOR, FORCE_COLOR, tty, expected
+            (Always, Some("1"), None, false, true),
+            (Always, None, Some("0"), false, true),
+            (Never, None, Some("1"), true, false),
+            (Never, None, None, true, false),
+            (Auto, None, None, true, true),
+            (Auto, None, None, false, false),
+            (Auto, Some("1"), None, true, false),
+            (Auto, Some("1"), Some("1"), true, false),
+            (Auto, Some(""), None, true, true),
+            (Auto, Some(""), Some("1"), false, true),
+            (Auto, None, Some("1"), false, true),
+            (Auto, None, Some("0"), false, false),
+            (Auto, None, Some("0"), true, true),
+            (Auto, None, Some(""), false, false),
+        ];
+        for (choice, no_color, force_color, tty, expected) in cases {
+            assert_eq!(
+                decide_color(choice, no_color, force_color, tty),
+                expected,
+                "{choice:?} NO_COLOR={no_color:?} FORCE_COLOR={force_color:?} tty={tty}"
+            );
+        }
+    }
+
+    #[test]
+    fn baseline_from_the_future_keeps_peaks() {
+        let args = Cli::parse_from(["ifstat-rs"]);
+        let stat = |rx: u64| -> DeviceStatistics {
+            return DeviceStatistics {
+                rx,
+                ..Default::default()
+            };
+        };
+        let mut baseline = StatisticsDb::new();
+        baseline.insert("eth0".to_string(), stat(1000));
+        let mut current = StatisticsDb::new();
+        current.insert("eth0".to_string(), stat(5000));
+        baseline.timestamp = current.timestamp + chrono::Duration::seconds(10);
+        let (diff, interval) =
+            compute_diff(Some(&baseline), &current, &args).unwrap();
+        assert_eq!(interval, 0_f64);
+        let mut peaks =
+            DeviceSpeeds::from([("eth0".to_string(), speed(100_f64, 0_f64))]);
+        update_peak_speeds(
+            &mut peaks,
+            &get_device_speeds(&diff, interval),
+            interval,
+        );
+        assert_eq!(peaks["eth0"].rx, 100_f64);
+    }
+
+    #[test]
+    fn delimited_rows_without_interval_have_empty_cells() {
+        let mut db = StatisticsDb::new();
+        db.insert(
+            "eth0".to_string(),
+            DeviceStatistics {
+                rx: 1000,
+                tx: 2000,
+                ..Default::default()
+            },
+        );
+        let mut out = Vec::new();
+        print_delimited_report(
+            &mut out,
+            &get_device_speeds(&db.devices, 0_f64),
+            &db,
+            SortKey::Name,
+            SortDirection::Natural,
+            true,
+            Delimiter::Comma,
+            &[
+                Field::Interface,
+                Field::RxBytesPerSec,
+                Field::TxBytesPerSec,
+                Field::RxTotal,
+            ],
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "eth0,,,1000\n");
+    }
+
+    #[test]
+    fn renders_aligned_table() {
+        let render = |argv: &[&str]| -> String {
+            let mut options = Cli::parse_from(argv).get_table_options();
+            options.color = false;
+            let mut db = StatisticsDb::new();
+            db.insert("wan0".to_string(), DeviceStatistics::default());
+            db.insert("br-lan".to_string(), DeviceStatistics::default());
+            let speeds = DeviceSpeeds::from([
+                ("wan0".to_string(), speed(1536_f64, 0_f64)),
+                ("br-lan".to_string(), speed(0_f64, 2.5e6)),
+            ]);
+            let mut out = Vec::new();
+            pretty_print_speed_table(
+                &mut out, &speeds, None, None, None, &db, &options,
+            )
+            .unwrap();
+            return String::from_utf8(out).unwrap();
+        };
+        assert_eq!(
+            render(&["ifstat-rs"]),
+            concat!(
+                " Interface          Receive                  Transmit         \n",
+                "    br-lan     0.00 B/s (0.00 bit/s) 2.38 MiB/s (20.00 Mbit/s)\n",
+                "      wan0 1.50 KiB/s (12.29 Kbit/s)     0.00 B/s (0.00 bit/s)\n",
+            )
+        );
+        assert_eq!(
+            render(&["ifstat-rs", "--hide-zero-values"]),
+            concat!(
+                " Interface          Receive                  Transmit         \n",
+                "    br-lan                           2.38 MiB/s (20.00 Mbit/s)\n",
+                "      wan0 1.50 KiB/s (12.29 Kbit/s)                          \n",
+            )
+        );
+    }
+
+    /// Writer whose reader has gone away
+    struct ClosedPipe;
+    impl Write for ClosedPipe {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            return Err(std::io::ErrorKind::BrokenPipe.into());
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            return Err(std::io::ErrorKind::BrokenPipe.into());
+        }
+    }
+
+    #[test]
+    fn every_format_reports_broken_pipe() {
+        let mut db = StatisticsDb::new();
+        db.insert("eth0".to_string(), DeviceStatistics::default());
+        let speeds = DeviceSpeeds::from([("eth0".to_string(), speed(1_f64, 2_f64))]);
+        for format in <OutputFormat as clap::ValueEnum>::value_variants() {
+            let value = clap::ValueEnum::to_possible_value(format).unwrap();
+            let args = Cli::parse_from(["ifstat-rs", "--format", value.get_name()]);
+            let result = print_stats(
+                &mut ClosedPipe,
+                &db.devices,
+                &speeds,
+                1_f64,
+                &db,
+                None,
+                None,
+                None,
+                &args,
+                &args.get_table_options(),
+            );
+            let e = result.expect_err("writing to a closed pipe succeeded");
+            assert!(is_broken_pipe(&e), "{format:?}: {e:#}");
+        }
+    }
+
+    #[test]
+    fn pads_non_ascii_aliases_by_characters() {
+        let mut options = Cli::parse_from(["ifstat-rs"]).get_table_options();
+        options.color = false;
+        options
+            .aliases
+            .insert("eth0".to_string(), "Wohnzimmer-Büro-Ärger".to_string());
+        let mut db = StatisticsDb::new();
+        db.insert("eth0".to_string(), DeviceStatistics::default());
+        db.insert("wan0".to_string(), DeviceStatistics::default());
+        let speeds = DeviceSpeeds::from([
+            ("eth0".to_string(), speed(0_f64, 0_f64)),
+            ("wan0".to_string(), speed(0_f64, 0_f64)),
+        ]);
+        let mut out = Vec::new();
+        pretty_print_speed_table(&mut out, &speeds, None, None, None, &db, &options)
+            .unwrap();
+        let table = String::from_utf8(out).unwrap();
+        let widths: Vec<usize> = table.lines().map(|x| x.chars().count()).collect();
+        assert_eq!(widths.len(), 3);
+        assert!(widths.iter().all(|x| *x == widths[0]), "{table}");
+        assert!(
+            table.lines().nth(1).unwrap().starts_with("Wohnzimmer"),
+            "{table}"
+        );
+    }
+
+    #[test]
+    fn parses_human_sizes() {
+        assert_eq!(parse_human_size("1500").unwrap(), 1500_f64);
+        assert_eq!(parse_human_size("500k").unwrap(), 5e5);
+        assert_eq!(parse_human_size("10m").unwrap(), 1e7);
+        assert_eq!(parse_human_size("10MB").unwrap(), 1e7);
+        assert_eq!(parse_human_size("1.5GiB").unwrap(), 1.5 * 1024_f64.powi(3));
+        assert_eq!(parse_human_size("2ti").unwrap(), 2_f64 * 1024_f64.powi(4));
+        assert!(parse_human_size("10x").is_err());
+        assert!(parse_human_size("10i").is_err());
+        assert!(parse_human_size("-1k").is_err());
+    }
 }
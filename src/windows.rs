@@ -0,0 +1,129 @@
+//! Interface counters from the GetIfTable2 IP Helper API
+
+use super::{DeviceStatistics, InterfaceFilter, StatisticsDb};
+
+const IF_MAX_STRING_SIZE: usize = 256;
+const IF_MAX_PHYS_ADDRESS_LENGTH: usize = 32;
+const NO_ERROR: u32 = 0;
+/// FilterInterface bit of MIB_IF_ROW2::InterfaceAndOperStatusFlags
+const FILTER_INTERFACE_FLAG: u8 = 0b10;
+
+#[repr(C)]
+#[allow(dead_code)]
+struct Guid {
+    data1: u32,
+    data2: u16,
+    data3: u16,
+    data4: [u8; 8],
+}
+
+/// MIB_IF_ROW2 from netioapi.h
+#[repr(C)]
+#[allow(dead_code)]
+struct MibIfRow2 {
+    interface_luid: u64,
+    interface_index: u32,
+    interface_guid: Guid,
+    alias: [u16; IF_MAX_STRING_SIZE + 1],
+    description: [u16; IF_MAX_STRING_SIZE + 1],
+    physical_address_length: u32,
+    physical_address: [u8; IF_MAX_PHYS_ADDRESS_LENGTH],
+    permanent_physical_address: [u8; IF_MAX_PHYS_ADDRESS_LENGTH],
+    mtu: u32,
+    if_type: u32,
+    tunnel_type: u32,
+    media_type: u32,
+    physical_medium_type: u32,
+    access_type: u32,
+    direction_type: u32,
+    interface_and_oper_status_flags: u8,
+    oper_status: u32,
+    admin_status: u32,
+    media_connect_state: u32,
+    network_guid: Guid,
+    connection_type: u32,
+    transmit_link_speed: u64,
+    receive_link_speed: u64,
+    in_octets: u64,
+    in_ucast_pkts: u64,
+    in_n_ucast_pkts: u64,
+    in_discards: u64,
+    in_errors: u64,
+    in_unknown_protos: u64,
+    in_ucast_octets: u64,
+    in_multicast_octets: u64,
+    in_broadcast_octets: u64,
+    out_octets: u64,
+    out_ucast_pkts: u64,
+    out_n_ucast_pkts: u64,
+    out_discards: u64,
+    out_errors: u64,
+    out_ucast_octets: u64,
+    out_multicast_octets: u64,
+    out_broadcast_octets: u64,
+    out_q_len: u64,
+}
+
+/// MIB_IF_TABLE2 with its variable length array of rows
+#[repr(C)]
+struct MibIfTable2 {
+    num_entries: u32,
+    table: [MibIfRow2; 1],
+}
+
+#[link(name = "iphlpapi")]
+extern "system" {
+    fn GetIfTable2(table: *mut *mut MibIfTable2) -> u32;
+    fn FreeMibTable(memory: *mut std::ffi::c_void);
+}
+
+fn from_wide(s: &[u16]) -> String {
+    let len = s.iter().position(|x| *x == 0).unwrap_or(s.len());
+    return String::from_utf16_lossy(&s[..len]);
+}
+
+/// Reads all interfaces except NDIS filter entries, keyed by their alias
+pub(crate) fn parse_if_table(
+    hide_zero_ifs: bool,
+    filter: &InterfaceFilter,
+) -> anyhow::Result<StatisticsDb> {
+    let mut table: *mut MibIfTable2 = std::ptr::null_mut();
+    let status = unsafe { GetIfTable2(&mut table) };
+    if status != NO_ERROR {
+        return Err(anyhow::anyhow!("GetIfTable2 failed with error {}", status));
+    }
+    let rows = unsafe {
+        std::slice::from_raw_parts(
+            (*table).table.as_ptr(),
+            (*table).num_entries as usize,
+        )
+    };
+    let mut ret = StatisticsDb::new();
+    for row in rows {
+        if row.interface_and_oper_status_flags & FILTER_INTERFACE_FLAG != 0 {
+            continue;
+        }
+        let ifname = from_wide(&row.alias);
+        if !filter.is_selected(&ifname) {
+            log::debug!("Interface '{ifname}' is filtered out. Ignoring");
+            continue;
+        }
+        let stat = DeviceStatistics {
+            rx: row.in_octets,
+            tx: row.out_octets,
+            rx_packets: row.in_ucast_pkts + row.in_n_ucast_pkts,
+            tx_packets: row.out_ucast_pkts + row.out_n_ucast_pkts,
+            rx_errors: row.in_errors,
+            tx_errors: row.out_errors,
+            rx_drops: row.in_discards,
+            tx_drops: row.out_discards,
+        };
+        if hide_zero_ifs && stat.rx.max(stat.tx) == 0 {
+            log::debug!("Interface '{ifname}' has zero statistics. Ignoring");
+            continue;
+        }
+        ret.insert(ifname, stat);
+    }
+    unsafe { FreeMibTable(table as *mut std::ffi::c_void) };
+    return Ok(ret);
+}